@@ -3,13 +3,16 @@ use pollster::FutureExt;
 use winit::dpi::PhysicalSize;
 use winit::{
     application::ApplicationHandler,
+    dpi::PhysicalPosition,
     event::*,
     event_loop::ActiveEventLoop,
-    keyboard::PhysicalKey,
+    keyboard::{KeyCode, PhysicalKey},
     window::{WindowAttributes, WindowId},
 };
 
+use crate::camera::{CameraMode, ViewPreset};
 use crate::state::State;
+use crate::timing::FrameTimer;
 
 // These do not matter for webassembly but do for desktop
 // The canvas element serves as our "base" size of the "window"
@@ -17,16 +20,36 @@ use crate::state::State;
 const SIZE: PhysicalSize<u32> = PhysicalSize::new(512, 512);
 #[cfg(not(target_arch = "wasm32"))]
 const TITLE: &str = "WGPU Program";
+// A left press and release closer than this (in pixels) is a click,
+// anything more is an orbit drag
+const CLICK_DISTANCE: f64 = 4.0;
 
 // winit application struct
 pub struct App {
     pub state: Option<State>,
+    timer: FrameTimer,
+    // Where the left button went down
+    press_position: Option<PhysicalPosition<f64>>,
+    // Model file given on the command line, loaded once the state exists
+    #[cfg(not(target_arch = "wasm32"))]
+    model_path: Option<std::path::PathBuf>,
+    // Cubemap from the SKYBOX environment variable, same deal
+    #[cfg(not(target_arch = "wasm32"))]
+    skybox_path: Option<std::path::PathBuf>,
 }
 
 impl App {
     pub fn new() -> Self {
         // State will be created later on
-        Self { state: None }
+        Self {
+            state: None,
+            timer: FrameTimer::new(),
+            press_position: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            model_path: std::env::args_os().nth(1).map(Into::into),
+            #[cfg(not(target_arch = "wasm32"))]
+            skybox_path: std::env::var_os("SKYBOX").map(Into::into),
+        }
     }
 }
 
@@ -39,14 +62,63 @@ impl ApplicationHandler for App {
         let window = event_loop.create_window(attrib).unwrap();
 
         // Create state
-        self.state = Some(State::new(window).block_on());
+        // A shader that doesn't match its layouts can't be recovered from
+        #[allow(unused_mut)]
+        let mut state = match State::new(window).block_on() {
+            Ok(state) => state,
+            Err(err) => {
+                log::error!("{err}");
+                event_loop.exit();
+                return;
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = self.model_path.take() {
+            let is_obj = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"));
+            // Anything that isn't an OBJ is handed to the glTF importer
+            let result = if is_obj {
+                state
+                    .open_obj(&path)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            } else {
+                state
+                    .open_gltf(&path)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            };
+            if let Err(err) = result {
+                log::error!("Couldn't load {}: {err}", path.display());
+            }
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = self.skybox_path.take()
+            && let Err(err) = state.open_skybox(&path)
+        {
+            log::error!("Couldn't load skybox {}: {err}", path.display());
+        }
+
+        self.state = Some(state);
+    }
+
+    // Fly camera mouse look, raw motion keeps coming while the cursor is locked
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _id: DeviceId, event: DeviceEvent) {
+        if let Some(state) = self.state.as_mut()
+            && let DeviceEvent::MouseMotion { delta } = event
+        {
+            state.camera_controller.process_mouse_motion(delta);
+        }
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, id: WindowId, event: WindowEvent) {
-        if let Some(state) = self.state.as_mut() {
-            if id != state.window.id() {
-                return;
-            }
+        if let Some(state) = self.state.as_mut()
+            && id != state.window.id()
+        {
+            return;
         }
 
         // Event handling
@@ -59,6 +131,7 @@ impl ApplicationHandler for App {
                     KeyEvent {
                         state,
                         physical_key: PhysicalKey::Code(keycode),
+                        repeat,
                         ..
                     },
                 ..
@@ -68,6 +141,94 @@ impl ApplicationHandler for App {
                     app_state
                         .camera_controller
                         .process_events(state.is_pressed(), keycode);
+
+                    // Render settings
+                    if state.is_pressed() && !repeat {
+                        match keycode {
+                            KeyCode::KeyM => {
+                                app_state.cycle_sample_count();
+                            }
+                            KeyCode::KeyV => app_state.cycle_render_mode(),
+                            KeyCode::KeyF => app_state.toggle_fly_camera(),
+                            KeyCode::KeyT => app_state.toggle_fixed_timestep(),
+                            KeyCode::KeyP => app_state.toggle_projection(),
+                            KeyCode::KeyZ => app_state.toggle_reverse_z(),
+                            KeyCode::KeyG => app_state.toggle_pick_mode(),
+                            KeyCode::KeyO => app_state.cycle_tonemap(),
+                            KeyCode::KeyN => app_state.toggle_cube_grid(),
+                            KeyCode::Minus | KeyCode::NumpadSubtract => {
+                                app_state.adjust_exposure(-1.0)
+                            }
+                            KeyCode::Equal | KeyCode::NumpadAdd => app_state.adjust_exposure(1.0),
+                            KeyCode::Digit1 | KeyCode::Numpad1 => {
+                                app_state.set_view(ViewPreset::Front)
+                            }
+                            KeyCode::Digit2 | KeyCode::Numpad3 => {
+                                app_state.set_view(ViewPreset::Side)
+                            }
+                            KeyCode::Digit3 | KeyCode::Numpad7 => {
+                                app_state.set_view(ViewPreset::Top)
+                            }
+                            KeyCode::Delete | KeyCode::Backspace => app_state.remove_selected(),
+                            KeyCode::Escape => app_state.set_camera_mode(CameraMode::Orbit),
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            // Orbit camera
+            WindowEvent::MouseInput { state, button, .. } => {
+                if let Some(app_state) = self.state.as_mut() {
+                    app_state
+                        .camera_controller
+                        .process_mouse_button(state, button);
+
+                    // Clicking takes the pointer back after the browser let go of it
+                    if state.is_pressed() && app_state.camera_controller.mode == CameraMode::Fly {
+                        app_state.grab_cursor();
+                    }
+
+                    // Picking, only when the mouse didn't move in between
+                    let cursor = app_state.camera_controller.cursor();
+                    if button == MouseButton::Left
+                        && app_state.camera_controller.mode == CameraMode::Orbit
+                    {
+                        if state.is_pressed() {
+                            self.press_position = cursor;
+                        } else if let (Some(pressed), Some(released)) =
+                            (self.press_position.take(), cursor)
+                            && (pressed.x - released.x).hypot(pressed.y - released.y)
+                                < CLICK_DISTANCE
+                        {
+                            app_state.select_at(released);
+                        }
+                    }
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(app_state) = self.state.as_mut() {
+                    app_state.camera_controller.process_cursor_moved(position);
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                if let Some(app_state) = self.state.as_mut() {
+                    app_state.camera_controller.process_cursor_left();
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                if let Some(app_state) = self.state.as_mut() {
+                    app_state.camera_controller.process_scroll(delta);
+                }
+            }
+            // Don't keep the cursor hostage when switching windows,
+            // fly mode stays on and grabs it again on the way back
+            WindowEvent::Focused(focused) => {
+                if let Some(app_state) = self.state.as_ref() {
+                    if !focused {
+                        app_state.release_cursor();
+                    } else if app_state.camera_controller.mode == CameraMode::Fly {
+                        app_state.grab_cursor();
+                    }
                 }
             }
             WindowEvent::Resized(physical_size) => {
@@ -79,7 +240,7 @@ impl ApplicationHandler for App {
                 // Redraw the window and gfx
                 if let Some(state) = self.state.as_mut() {
                     state.window.request_redraw();
-                    state.update();
+                    state.update(self.timer.tick());
 
                     match state.render() {
                         Ok(_) => {}
@@ -1,35 +1,89 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use std::f32::consts;
 
-use winit::keyboard::KeyCode;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, MouseButton, MouseScrollDelta},
+    keyboard::KeyCode,
+};
 
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::from_cols_slice(&[
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
-]);
+use crate::picking::Ray;
 
+// Radians per pixel of mouse movement
+const ORBIT_SENSITIVITY: f32 = 0.005;
+// Pan distance per pixel as a fraction of the visible height at the
+// target, so the grabbed point roughly stays under the cursor
+const PAN_SENSITIVITY: f32 = 0.002;
+// How much one scroll line zooms, a fraction of the distance to the
+// target or of the orthographic extent
+const ZOOM_SENSITIVITY: f32 = 0.1;
+// Touchpads scroll in pixels, this is about one line
+const PIXELS_PER_LINE: f32 = 50.0;
+// Keep the eye from going straight over the target, look_at flips there
+const MAX_PITCH: f32 = consts::FRAC_PI_2 - 0.01;
+const MIN_DISTANCE: f32 = 0.1;
+const MIN_ORTHO_HEIGHT: f32 = 0.01;
+pub const DEFAULT_FOVY: f32 = consts::FRAC_PI_4;
+// Radians per unit of raw mouse motion in fly mode
+const LOOK_SENSITIVITY: f32 = 0.002;
+const SPRINT_MULTIPLIER: f32 = 3.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    // Vertical field of view in radians
+    Perspective { fovy: f32 },
+    // Vertical extent in world units, width follows the aspect ratio
+    Orthographic { height: f32 },
+}
+
+// Axis aligned views looking at the target, like a modeling tool
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ViewPreset {
+    // Looking down -Z
+    Front,
+    // Looking down -X
+    Side,
+    // Looking down -Y with -Z up
+    Top,
+}
+
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub eye: Vec3,
     pub target: Vec3,
     pub up: Vec3,
     pub aspect: f32,
-    pub fovy: f32,
+    pub projection: Projection,
     pub znear: f32,
     pub zfar: f32,
+    // Depth goes from 1 at znear to 0 at infinity, perspective then ignores
+    // zfar. Needs the matching DepthConfig::reversed()
+    pub reverse_z: bool,
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 pub struct CameraUniform {
     pub view_proj: [[f32; 4]; 4],
+    pub view_pos: [f32; 4],
+}
+
+// Orbit circles Camera::target, Fly moves the eye freely
+// with the target kept in front of it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    Orbit,
+    Fly,
 }
 
 pub struct CameraController {
+    pub mode: CameraMode,
+    // Units per second
     pub speed: f32,
+    // Radians per second for keyboard turning
+    pub turn_speed: f32,
     pub up_pressed: bool,
     pub down_pressed: bool,
     pub left_pressed: bool,
@@ -39,6 +93,17 @@ pub struct CameraController {
     pub j_pressed: bool,
     pub l_pressed: bool,
     pub reset_pressed: bool,
+    pub q_pressed: bool,
+    pub e_pressed: bool,
+    pub sprint_pressed: bool,
+    // Mouse state, drags get accumulated here until the next update
+    orbiting: bool,
+    panning: bool,
+    cursor: Option<PhysicalPosition<f64>>,
+    orbit_delta: Vec2,
+    pan_delta: Vec2,
+    scroll: f32,
+    look_delta: Vec2,
 }
 
 impl Camera {
@@ -49,21 +114,188 @@ impl Camera {
             target: Vec3::ZERO,
             up: Vec3::Y, // Y-up unit vector
             aspect,
-            fovy: consts::FRAC_PI_4,
+            projection: Projection::Perspective { fovy: DEFAULT_FOVY },
             znear: 0.1,
             zfar: 100.0,
+            reverse_z: false,
         }
     }
     pub fn reset_view(&mut self) {
         self.eye = Vec3::new(0.0, 0.0, 2.0);
         self.target = Vec3::ZERO;
+        self.up = Vec3::Y;
+    }
+
+    // Keeps the target and distance, only the direction changes
+    pub fn set_view(&mut self, preset: ViewPreset) {
+        let distance = (self.eye - self.target).length().max(MIN_DISTANCE);
+        let (direction, up) = match preset {
+            ViewPreset::Front => (Vec3::Z, Vec3::Y),
+            ViewPreset::Side => (Vec3::X, Vec3::Y),
+            // Straight down, look_at needs an up that isn't the view direction
+            ViewPreset::Top => (Vec3::Y, Vec3::NEG_Z),
+        };
+        self.eye = self.target + direction * distance;
+        self.up = up;
+    }
+
+    // Switches between perspective and orthographic keeping roughly
+    // the same part of the scene in view
+    pub fn toggle_projection(&mut self) {
+        match self.projection {
+            Projection::Perspective { .. } => {
+                self.projection = Projection::Orthographic {
+                    height: self.view_height(),
+                };
+            }
+            Projection::Orthographic { height } => {
+                self.projection = Projection::Perspective { fovy: DEFAULT_FOVY };
+                let distance = height / (2.0 * (DEFAULT_FOVY / 2.0).tan());
+                let offset = self.eye - self.target;
+                self.eye = self.target + offset.normalize_or(Vec3::Z) * distance;
+            }
+        }
     }
+
+    // Visible height in world units at the target's distance
+    pub fn view_height(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective { fovy } => {
+                2.0 * (self.eye - self.target).length() * (fovy / 2.0).tan()
+            }
+            Projection::Orthographic { height } => height,
+        }
+    }
+
+    // Rotates the eye around the target, yaw around the world up axis and
+    // pitch towards it. Pitch is clamped short of straight up or down
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        // Leaving a top view
+        self.up = Vec3::Y;
+        if let Some(offset) = rotate(self.eye - self.target, yaw, pitch) {
+            self.eye = self.target + offset;
+        }
+    }
+
+    // Rotates the target around the eye, the fly camera looking around
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.up = Vec3::Y;
+        if let Some(offset) = rotate(self.target - self.eye, yaw, pitch) {
+            self.target = self.eye + offset;
+        }
+    }
+
+    // Moves eye and target together in the view plane
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let forward = (self.target - self.eye).normalize();
+        let right_dir = forward.cross(self.up).normalize();
+        let up_dir = right_dir.cross(forward);
+        let offset = right_dir * right + up_dir * up;
+
+        self.eye += offset;
+        self.target += offset;
+    }
+
+    // Moves the eye towards the target, never past it
+    pub fn dolly(&mut self, amount: f32) {
+        let offset = self.eye - self.target;
+        let distance = (offset.length() - amount).max(MIN_DISTANCE);
+        self.eye = self.target + offset.normalize() * distance;
+    }
+
+    // Positive zooms in by that fraction. Moving the eye does nothing
+    // for orthographic, the extent shrinks instead
+    pub fn zoom(&mut self, fraction: f32) {
+        match &mut self.projection {
+            Projection::Perspective { .. } => {
+                let distance = (self.eye - self.target).length();
+                self.dolly(fraction * distance);
+            }
+            Projection::Orthographic { height } => {
+                *height = (*height * (1.0 - fraction)).max(MIN_ORTHO_HEIGHT);
+            }
+        }
+    }
+
+    // Blends the pose and zoom towards `other`. Switching between
+    // perspective and orthographic can't be blended, that snaps
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        let projection = match (self.projection, other.projection) {
+            (Projection::Perspective { fovy: a }, Projection::Perspective { fovy: b }) => {
+                Projection::Perspective {
+                    fovy: a + (b - a) * t,
+                }
+            }
+            (Projection::Orthographic { height: a }, Projection::Orthographic { height: b }) => {
+                Projection::Orthographic {
+                    height: a + (b - a) * t,
+                }
+            }
+            (_, projection) => projection,
+        };
+
+        Camera {
+            eye: self.eye.lerp(other.eye, t),
+            target: self.target.lerp(other.target, t),
+            projection,
+            znear: self.znear + (other.znear - self.znear) * t,
+            zfar: self.zfar + (other.zfar - self.zfar) * t,
+            ..other.clone()
+        }
+    }
+
+    // Right handed. glam's *_rh matrices already use the 0..1 depth
+    // range wgpu wants, no OpenGL style conversion needed on top
+    pub fn projection_matrix(&self) -> Mat4 {
+        match self.projection {
+            Projection::Perspective { fovy } if self.reverse_z => {
+                Mat4::perspective_infinite_reverse_rh(fovy, self.aspect, self.znear)
+            }
+            Projection::Perspective { fovy } => {
+                Mat4::perspective_rh(fovy, self.aspect, self.znear, self.zfar)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                let ortho = |near, far| {
+                    Mat4::orthographic_rh(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        near,
+                        far,
+                    )
+                };
+                // No infinite far plane here, swapping near and far still reverses it
+                if self.reverse_z {
+                    ortho(self.zfar, self.znear)
+                } else {
+                    ortho(self.znear, self.zfar)
+                }
+            }
+        }
+    }
+
+    // World space ray through a pixel, `cursor` and `size` in pixels with
+    // the origin top left. Starts on the near plane
+    pub fn ray(&self, cursor: Vec2, size: Vec2) -> Ray {
+        let ndc = Vec2::new(cursor.x / size.x * 2.0 - 1.0, 1.0 - cursor.y / size.y * 2.0);
+
+        // Whatever depth range the projection uses (reversed, infinite),
+        // these are the depths of the near plane and a bit past it
+        let projection = self.projection_matrix();
+        let depth = |distance: f32| projection.project_point3(Vec3::new(0.0, 0.0, -distance)).z;
+
+        let inverse = self.view_proj_matrix().inverse();
+        let near = inverse.project_point3(ndc.extend(depth(self.znear)));
+        let far = inverse.project_point3(ndc.extend(depth(self.znear * 2.0)));
+        Ray::new(near, (far - near).normalize())
+    }
+
     pub fn view_proj_matrix(&self) -> Mat4 {
-        // Right hand perspective
         let view = Mat4::look_at_rh(self.eye, self.target, self.up);
-        let proj = Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar);
-
-        proj * view
+        self.projection_matrix() * view
     }
 }
 
@@ -71,18 +303,20 @@ impl CameraUniform {
     pub fn new() -> Self {
         Self {
             view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            view_pos: [0.0; 4],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.view_proj_matrix()).to_cols_array_2d();
+        self.view_proj = camera.view_proj_matrix().to_cols_array_2d();
+        self.view_pos = camera.eye.extend(1.0).to_array();
     }
 
     pub fn bind_desc<'a>(&self) -> wgpu::BindGroupLayoutDescriptor<'a> {
         wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -96,9 +330,11 @@ impl CameraUniform {
 }
 
 impl CameraController {
-    pub fn new(speed: f32) -> Self {
+    pub fn new(speed: f32, turn_speed: f32) -> Self {
         Self {
+            mode: CameraMode::default(),
             speed,
+            turn_speed,
             up_pressed: false,
             down_pressed: false,
             left_pressed: false,
@@ -108,6 +344,16 @@ impl CameraController {
             j_pressed: false,
             l_pressed: false,
             reset_pressed: false,
+            q_pressed: false,
+            e_pressed: false,
+            sprint_pressed: false,
+            orbiting: false,
+            panning: false,
+            cursor: None,
+            orbit_delta: Vec2::ZERO,
+            pan_delta: Vec2::ZERO,
+            scroll: 0.0,
+            look_delta: Vec2::ZERO,
         }
     }
 
@@ -149,65 +395,267 @@ impl CameraController {
                 self.reset_pressed = pressed;
                 true
             }
+            KeyCode::KeyQ => {
+                self.q_pressed = pressed;
+                true
+            }
+            KeyCode::KeyE => {
+                self.e_pressed = pressed;
+                true
+            }
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => {
+                self.sprint_pressed = pressed;
+                true
+            }
             _ => false,
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    // Left drag orbits, right or middle drag pans
+    pub fn process_mouse_button(&mut self, state: ElementState, button: MouseButton) -> bool {
+        let pressed = state.is_pressed();
+        match button {
+            MouseButton::Left => {
+                self.orbiting = pressed;
+                true
+            }
+            MouseButton::Right | MouseButton::Middle => {
+                self.panning = pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn process_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        if let Some(last) = self.cursor {
+            let delta = Vec2::new((position.x - last.x) as f32, (position.y - last.y) as f32);
+            // Fly mode looks around with raw mouse motion instead
+            if self.mode == CameraMode::Orbit {
+                if self.orbiting {
+                    self.orbit_delta += delta;
+                } else if self.panning {
+                    self.pan_delta += delta;
+                }
+            }
+        }
+        self.cursor = Some(position);
+    }
+
+    pub fn cursor(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor
+    }
+
+    // Otherwise the first move after re-entering jumps
+    pub fn process_cursor_left(&mut self) {
+        self.cursor = None;
+        self.orbiting = false;
+        self.panning = false;
+    }
+
+    // Raw device motion, keeps working when the cursor is locked in place
+    pub fn process_mouse_motion(&mut self, (dx, dy): (f64, f64)) {
+        if self.mode == CameraMode::Fly {
+            self.look_delta += Vec2::new(dx as f32, dy as f32);
+        }
+    }
+
+    pub fn process_scroll(&mut self, delta: MouseScrollDelta) {
+        self.scroll += match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+        };
+    }
+
+    // `dt` is in seconds, mouse input isn't scaled by it since
+    // the deltas already cover however long the frame took
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
         if self.reset_pressed {
             camera.reset_view();
         }
 
-        // Forward direction related vectors
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.length();
+        match self.mode {
+            CameraMode::Orbit => self.update_orbit(camera, dt),
+            CameraMode::Fly => self.update_fly(camera, dt),
+        }
+    }
 
-        // Forward backward movement
-        if self.up_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+    fn update_orbit(&mut self, camera: &mut Camera, dt: f32) {
+        // Mouse input, the scene follows the cursor so dragging
+        // down brings the top of it into view
+        if self.orbit_delta != Vec2::ZERO {
+            camera.orbit(
+                -self.orbit_delta.x * ORBIT_SENSITIVITY,
+                self.orbit_delta.y * ORBIT_SENSITIVITY,
+            );
         }
-        if self.down_pressed {
-            camera.eye -= forward_norm * self.speed;
+        if self.pan_delta != Vec2::ZERO {
+            let scale = camera.view_height() * PAN_SENSITIVITY;
+            camera.pan(-self.pan_delta.x * scale, self.pan_delta.y * scale);
+        }
+        if self.scroll != 0.0 {
+            camera.zoom(self.scroll * ZOOM_SENSITIVITY);
         }
+        self.orbit_delta = Vec2::ZERO;
+        self.pan_delta = Vec2::ZERO;
+        self.scroll = 0.0;
 
-        // Other directions
-        let right = forward_norm.cross(camera.up).normalize();
-        let up = forward_norm.cross(right).normalize();
+        let step = self.speed * dt;
+        let turn = self.turn_speed * dt;
 
-        // Left and right panning
-        // Right now only moves the "eye" directly
-        // TODO: Make it so that it moves relative to the target, similar to moving forward
-        if self.right_pressed {
-            camera.eye.x += self.speed;
-            camera.target.x += self.speed;
+        // Forward backward movement, as a zoom so it also works orthographic
+        let distance = (camera.target - camera.eye).length().max(MIN_DISTANCE);
+        if self.up_pressed {
+            camera.zoom(step / distance);
+        }
+        if self.down_pressed {
+            camera.zoom(-step / distance);
         }
 
+        // Left and right panning, target moves along so the view direction stays
+        if self.right_pressed {
+            camera.pan(step, 0.0);
+        }
         if self.left_pressed {
-            camera.eye.x -= self.speed;
-            camera.target.x -= self.speed;
+            camera.pan(-step, 0.0);
         }
 
-        // Get the forward direction and magnitude again
-        let forward = camera.target - camera.eye;
-        let forward_mag = forward.length();
-
         // Left right rotation
         if self.j_pressed {
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.orbit(-turn, 0.0);
         }
-
         if self.l_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            camera.orbit(turn, 0.0);
         }
 
         // Up down rotation
         if self.i_pressed {
-            camera.eye = camera.target - (forward + up * self.speed).normalize() * forward_mag;
+            camera.orbit(0.0, turn);
         }
-
         if self.k_pressed {
-            camera.eye = camera.target - (forward - up * self.speed).normalize() * forward_mag;
+            camera.orbit(0.0, -turn);
+        }
+    }
+
+    fn update_fly(&mut self, camera: &mut Camera, dt: f32) {
+        // Moving the mouse right or up looks right or up
+        let mut look = -self.look_delta * LOOK_SENSITIVITY;
+        self.look_delta = Vec2::ZERO;
+        // Drags and scrolling are orbit only
+        self.orbit_delta = Vec2::ZERO;
+        self.pan_delta = Vec2::ZERO;
+        self.scroll = 0.0;
+
+        // IJKL turning still works without a mouse
+        let turn_axis = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+        let turn = self.turn_speed * dt;
+        look.x += turn_axis(self.j_pressed, self.l_pressed) * turn;
+        look.y += turn_axis(self.i_pressed, self.k_pressed) * turn;
+        if look != Vec2::ZERO {
+            camera.turn(look.x, look.y);
+        }
+
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let movement = forward * turn_axis(self.up_pressed, self.down_pressed)
+            + right * turn_axis(self.right_pressed, self.left_pressed)
+            + Vec3::Y * turn_axis(self.e_pressed, self.q_pressed);
+
+        if movement != Vec3::ZERO {
+            let speed = if self.sprint_pressed {
+                self.speed * SPRINT_MULTIPLIER
+            } else {
+                self.speed
+            };
+            let offset = movement.normalize() * speed * dt;
+            camera.eye += offset;
+            camera.target += offset;
+        }
+    }
+}
+
+// Applies yaw (around world Y) and pitch to an offset vector,
+// keeping its length. None when the offset is zero
+fn rotate(offset: Vec3, yaw: f32, pitch: f32) -> Option<Vec3> {
+    let distance = offset.length();
+    if distance <= f32::EPSILON {
+        return None;
+    }
+
+    let yaw = offset.x.atan2(offset.z) + yaw;
+    let pitch =
+        ((offset.y / distance).clamp(-1.0, 1.0).asin() + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+
+    let direction = Vec3::new(
+        pitch.cos() * yaw.sin(),
+        pitch.sin(),
+        pitch.cos() * yaw.cos(),
+    );
+    Some(direction * distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    #[test]
+    fn center_ray_looks_at_target() {
+        for reverse_z in [false, true] {
+            let mut camera = Camera::new(1.0);
+            camera.reverse_z = reverse_z;
+
+            let ray = camera.ray(Vec2::splat(32.0), Vec2::splat(64.0));
+            // Starts on the near plane
+            let near = camera.eye + Vec3::NEG_Z * camera.znear;
+            assert!(ray.origin.abs_diff_eq(near, EPSILON), "{ray:?}");
+            assert!(ray.direction.abs_diff_eq(Vec3::NEG_Z, EPSILON), "{ray:?}");
         }
     }
+
+    #[test]
+    fn corner_ray_follows_fov() {
+        let camera = Camera::new(2.0);
+        let ray = camera.ray(Vec2::ZERO, Vec2::new(128.0, 64.0));
+
+        let half_height = (DEFAULT_FOVY / 2.0).tan();
+        let expected = Vec3::new(-half_height * 2.0, half_height, -1.0).normalize();
+        assert!(ray.direction.abs_diff_eq(expected, EPSILON), "{ray:?}");
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut camera = Camera::new(2.0);
+        camera.projection = Projection::Orthographic { height: 2.0 };
+
+        let ray = camera.ray(Vec2::ZERO, Vec2::new(128.0, 64.0));
+        assert!(ray.direction.abs_diff_eq(Vec3::NEG_Z, EPSILON), "{ray:?}");
+        assert!(
+            ray.origin
+                .truncate()
+                .abs_diff_eq(Vec2::new(-2.0, 1.0), EPSILON)
+        );
+    }
+
+    #[test]
+    fn lerp_blends_the_zoom() {
+        let mut previous = Camera::new(1.0);
+        previous.projection = Projection::Orthographic { height: 2.0 };
+        let mut current = previous.clone();
+        current.projection = Projection::Orthographic { height: 4.0 };
+        current.eye += Vec3::X * 2.0;
+
+        let halfway = previous.lerp(&current, 0.5);
+        assert_eq!(halfway.projection, Projection::Orthographic { height: 3.0 });
+        assert!(halfway.eye.abs_diff_eq(previous.eye + Vec3::X, EPSILON));
+
+        // Nothing in between perspective and orthographic
+        current.projection = Projection::Perspective { fovy: DEFAULT_FOVY };
+        assert_eq!(previous.lerp(&current, 0.5).projection, current.projection);
+    }
 }
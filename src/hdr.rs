@@ -0,0 +1,307 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::preprocessor::Preprocessor;
+use crate::reflect::{ReflectError, ShaderReflection};
+use crate::texture::Texture;
+use crate::viewport::{Viewport, ViewportDependent};
+
+const TONEMAP_SHADER: &str = "tonemap.wgsl";
+
+// How far one press of the exposure keys moves it, in stops
+pub const EXPOSURE_STEP: f32 = 0.5;
+
+// The scene gets rendered in linear HDR and only squashed into
+// displayable range by the tonemapping pass at the very end.
+// Half floats aren't renderable everywhere (WebGL2 without
+// EXT_color_buffer_float), there it falls back to 8 bit and loses the range
+pub fn hdr_format(adapter: &wgpu::Adapter) -> wgpu::TextureFormat {
+    let features = adapter.get_texture_format_features(wgpu::TextureFormat::Rgba16Float);
+    if features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        && features
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::BLENDABLE)
+    {
+        wgpu::TextureFormat::Rgba16Float
+    } else {
+        log::warn!("Rgba16Float isn't renderable here, rendering the scene in Rgba8Unorm");
+        wgpu::TextureFormat::Rgba8Unorm
+    }
+}
+
+// Has to match the operator ids in tonemap.wgsl
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Tonemap {
+    Reinhard,
+    #[default]
+    AcesFilmic,
+    AgX,
+}
+
+impl Tonemap {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemap::Reinhard => Tonemap::AcesFilmic,
+            Tonemap::AcesFilmic => Tonemap::AgX,
+            Tonemap::AgX => Tonemap::Reinhard,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TonemapConfig {
+    pub tonemap: Tonemap,
+    // In stops, every +1 doubles the brightness
+    pub exposure: f32,
+}
+
+impl Default for TonemapConfig {
+    fn default() -> Self {
+        Self {
+            tonemap: Tonemap::default(),
+            exposure: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct TonemapUniform {
+    // exposure multiplier, operator id
+    params: [f32; 4],
+}
+
+impl From<&TonemapConfig> for TonemapUniform {
+    fn from(config: &TonemapConfig) -> Self {
+        let operator = match config.tonemap {
+            Tonemap::Reinhard => 0.0,
+            Tonemap::AcesFilmic => 1.0,
+            Tonemap::AgX => 2.0,
+        };
+        Self {
+            params: [config.exposure.exp2(), operator, 0.0, 0.0],
+        }
+    }
+}
+
+// Offscreen HDR color target plus the pass that resolves it to the surface
+pub struct HdrTarget {
+    pub config: TonemapConfig,
+    pub texture: Texture,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // sRGB view format of the surface the pass writes to
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    output_format: wgpu::TextureFormat,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pipeline_layout: wgpu::PipelineLayout,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    shader: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl HdrTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        viewport: &Viewport,
+        surface_format: wgpu::TextureFormat,
+        config: TonemapConfig,
+    ) -> Result<Self, ReflectError> {
+        ShaderReflection::embedded(TONEMAP_SHADER)
+            .and_then(|reflection| reflection.validate(&[Self::bind_desc()], &[]))?;
+
+        let texture = Self::create_texture(device, viewport);
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap buffer"),
+            contents: bytemuck::cast_slice(&[TonemapUniform::from(&config)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&Self::bind_desc());
+
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture, &buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = Preprocessor::standard().load_embedded(device, TONEMAP_SHADER);
+        let output_format = surface_format.add_srgb_suffix();
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, &shader, output_format);
+
+        Ok(Self {
+            config,
+            texture,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            output_format,
+            pipeline_layout,
+            shader,
+            pipeline,
+        })
+    }
+
+    // Swaps in a new shader, the pipeline needs a rebuild after
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replace_shader(&mut self, shader: wgpu::ShaderModule) -> wgpu::ShaderModule {
+        std::mem::replace(&mut self.shader, shader)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.output_format,
+        );
+    }
+
+    pub fn set_config(&mut self, queue: &wgpu::Queue, config: TonemapConfig) {
+        self.config = config;
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[TonemapUniform::from(&self.config)]),
+        );
+    }
+
+    // View the scene passes should render into
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    // Tonemaps the HDR target onto `output`, which should be the sRGB surface view
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut tonemap_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        tonemap_pass.set_pipeline(&self.pipeline);
+        tonemap_pass.set_bind_group(0, &self.bind_group, &[]);
+        tonemap_pass.draw(0..3, 0..1);
+    }
+
+    // The HDR texture is read with textureLoad, no sampler
+    // needed and it doesn't have to be filterable
+    fn bind_desc<'a>() -> wgpu::BindGroupLayoutDescriptor<'a> {
+        wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        output_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_texture(device: &wgpu::Device, viewport: &Viewport) -> Texture {
+        Texture::create_render_target(
+            device,
+            viewport.size.width,
+            viewport.size.height,
+            viewport.hdr_format,
+            1,
+            Some("hdr_texture"),
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        })
+    }
+}
+
+// Single sampled whatever the MSAA setting, only the size matters
+impl ViewportDependent for HdrTarget {
+    fn viewport_changed(&mut self, device: &wgpu::Device, viewport: &Viewport) {
+        self.texture = Self::create_texture(device, viewport);
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.texture, &self.buffer);
+    }
+}
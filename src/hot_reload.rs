@@ -0,0 +1,105 @@
+// Dev mode shader reloading, desktop only. Set SHADER_HOT_RELOAD to turn it
+// on, shaders then get read from src/shaders/ (or the directory the variable
+// points to) and rebuilt whenever one of them changes. Only the shaders in
+// State's RELOADABLE_SHADERS get swapped in, the ID buffer and outline
+// passes still need a restart to pick up changes
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use pollster::FutureExt;
+
+pub const ENV_VAR: &str = "SHADER_HOT_RELOAD";
+const DEFAULT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+// No point hitting the file system every frame
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: HashMap<String, SystemTime>,
+    last_poll: Option<Instant>,
+}
+
+impl ShaderWatcher {
+    // Returns None unless the environment variable is set, a value
+    // other than "1" is used as the shader directory
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(ENV_VAR).ok()?;
+        let dir = match value.as_str() {
+            "" | "1" => PathBuf::from(DEFAULT_DIR),
+            dir => PathBuf::from(dir),
+        };
+
+        log::info!("Watching {} for shader changes", dir.display());
+        Some(Self {
+            dir,
+            modified: HashMap::new(),
+            last_poll: None,
+        })
+    }
+
+    // File names of the shaders that changed since the last poll. The very
+    // first poll reports every shader so the on disk versions replace the
+    // embedded ones right away
+    pub fn poll(&mut self) -> Vec<String> {
+        if self
+            .last_poll
+            .is_some_and(|last| last.elapsed() < POLL_INTERVAL)
+        {
+            return Vec::new();
+        }
+        self.last_poll = Some(Instant::now());
+
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Can't read {}: {err}", self.dir.display());
+                return Vec::new();
+            }
+        };
+
+        let mut changed = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "wgsl") {
+                continue;
+            }
+            let (Some(name), Ok(modified)) = (
+                path.file_name().and_then(|name| name.to_str()),
+                entry.metadata().and_then(|meta| meta.modified()),
+            ) else {
+                continue;
+            };
+
+            if self.modified.get(name) != Some(&modified) {
+                self.modified.insert(name.to_string(), modified);
+                changed.push(name.to_string());
+            }
+        }
+        changed
+    }
+
+    pub fn read(&self, name: &str) -> io::Result<String> {
+        fs::read_to_string(self.dir.join(name))
+    }
+}
+
+// wgpu treats shader errors as fatal unless they happen inside an error
+// scope, run the source through ShaderReflection first for readable ones
+pub fn compile(device: &wgpu::Device, name: &str, source: &str) -> Option<wgpu::ShaderModule> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(name),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    match device.pop_error_scope().block_on() {
+        Some(err) => {
+            log::error!("{name}: {err}");
+            None
+        }
+        None => Some(shader),
+    }
+}
@@ -0,0 +1,375 @@
+// GPU picking. Object IDs and instance indices get rendered into a single
+// pixel Rg32Uint target, the projection is zoomed in so that pixel covers
+// the one under the cursor. It's copied into a staging buffer that gets
+// mapped asynchronously, the result shows up a frame or two later without
+// State::render ever waiting on the GPU
+use std::sync::{Arc, OnceLock};
+
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::camera::CameraUniform;
+use crate::instance::InstanceRaw;
+use crate::preprocessor::Preprocessor;
+use crate::reflect::{ReflectError, ShaderReflection};
+use crate::scene::{ObjectId, ObjectUniform, Scene, Selection};
+use crate::vert::Vert;
+use crate::viewport::{Viewport, ViewportDependent};
+
+const ID_SHADER: &str = "id.wgsl";
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Uint;
+// Texture to buffer copies need rows aligned to this, even for one pixel
+const ROW_BYTES: u32 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+// Where a pick request is at
+enum Readback {
+    // Waiting for the next render
+    Requested { x: u32, y: u32 },
+    // Copy recorded, map_async goes out once it's submitted
+    Copied,
+    // Filled in by the map_async callback
+    Mapping(Arc<OnceLock<Result<(), wgpu::BufferAsyncError>>>),
+}
+
+pub struct IdBuffer {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    staging: wgpu::Buffer,
+    // Camera with the pick matrix applied, the main one is left alone
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+    viewport: Viewport,
+    readback: Option<Readback>,
+    // A request that came in while another was still mapping
+    queued: Option<(u32, u32)>,
+}
+
+impl IdBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        object_layout: &wgpu::BindGroupLayout,
+        viewport: &Viewport,
+    ) -> Result<Self, ReflectError> {
+        ShaderReflection::embedded(ID_SHADER).and_then(|reflection| {
+            reflection.validate(
+                &[CameraUniform::new().bind_desc(), ObjectUniform::bind_desc()],
+                &[Vert::desc(), InstanceRaw::desc()],
+            )
+        })?;
+        let shader = Preprocessor::standard().load_embedded(device, ID_SHADER);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ID Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, object_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, &shader, viewport);
+        let (texture, view, depth_view) = Self::create_targets(device, viewport);
+        let staging = Self::create_staging(device);
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ID camera buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("id_camera_bind_group"),
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            depth_view,
+            staging,
+            camera_buffer,
+            camera_bind_group,
+            pipeline_layout,
+            shader,
+            pipeline,
+            viewport: *viewport,
+            readback: None,
+            queued: None,
+        })
+    }
+
+    // Asks for the object at a pixel, the answer comes out of `poll`.
+    // A newer request replaces one that hasn't been rendered yet.
+    // Nothing to pick while the window is minimized (0x0)
+    pub fn request(&mut self, x: u32, y: u32) {
+        let size = self.viewport.size;
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        let x = x.min(size.width - 1);
+        let y = y.min(size.height - 1);
+        match self.readback {
+            None | Some(Readback::Requested { .. }) => {
+                self.readback = Some(Readback::Requested { x, y })
+            }
+            Some(_) => self.queued = Some((x, y)),
+        }
+    }
+
+    // Renders the requested pixel as seen through `camera`
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &CameraUniform,
+        scene: &Scene,
+    ) {
+        let Some(Readback::Requested { x, y }) = self.readback else {
+            return;
+        };
+
+        let view_proj = self.pick_matrix(x, y) * Mat4::from_cols_array_2d(&camera.view_proj);
+        let camera = CameraUniform {
+            view_proj: view_proj.to_cols_array_2d(),
+            ..*camera
+        };
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+
+        {
+            let mut id_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("ID Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // 0 is the background
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.viewport.depth_config.far_depth()),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            id_pass.set_pipeline(&self.pipeline);
+            id_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            scene.draw_depth(&mut id_pass, 1);
+        }
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.staging,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(ROW_BYTES),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.readback = Some(Readback::Copied);
+    }
+
+    // Has to come after the queue submit that holds the copy,
+    // a buffer can't be mapped while a submission still uses it
+    pub fn submitted(&mut self) {
+        if !matches!(self.readback, Some(Readback::Copied)) {
+            return;
+        }
+
+        let mapped = Arc::new(OnceLock::new());
+        let callback_mapped = mapped.clone();
+        self.staging
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = callback_mapped.set(result);
+            });
+        self.readback = Some(Readback::Mapping(mapped));
+    }
+
+    // Some(result) once a pick finished, the result being the object
+    // instance that was under the cursor. Never blocks
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Option<Selection>> {
+        let Some(Readback::Mapping(mapped)) = &self.readback else {
+            return None;
+        };
+
+        // Runs the map callbacks on desktop, browsers do that on their own
+        device.poll(wgpu::Maintain::Poll);
+        let result = match mapped.get()? {
+            Ok(()) => {
+                let (id, instance) = {
+                    let data = self.staging.slice(..).get_mapped_range();
+                    let texel: &[u32] = bytemuck::cast_slice(&data[..8]);
+                    (texel[0], texel[1])
+                };
+                self.staging.unmap();
+                Some(ObjectId::from_pick_id(id).map(|object| Selection {
+                    object,
+                    instance: instance as usize,
+                }))
+            }
+            // A failed map leaves nothing to unmap, a fresh buffer makes
+            // sure the next pick doesn't trip over whatever state it's in
+            Err(err) => {
+                log::error!("ID readback failed: {err}");
+                self.staging = Self::create_staging(device);
+                None
+            }
+        };
+
+        // Goes through request again, the size might have changed since
+        self.readback = None;
+        if let Some((x, y)) = self.queued.take() {
+            self.request(x, y);
+        }
+        result
+    }
+
+    // Scales and shifts clip space so pixel (x, y) of the viewport
+    // fills the whole one pixel target
+    fn pick_matrix(&self, x: u32, y: u32) -> Mat4 {
+        let width = self.viewport.size.width as f32;
+        let height = self.viewport.size.height as f32;
+        // Pixel center in NDC, y points up there
+        let center_x = (x as f32 + 0.5) / width * 2.0 - 1.0;
+        let center_y = 1.0 - (y as f32 + 0.5) / height * 2.0;
+        Mat4::from_scale(Vec3::new(width, height, 1.0))
+            * Mat4::from_translation(Vec3::new(-center_x, -center_y, 0.0))
+    }
+
+    fn create_staging(device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ID staging buffer"),
+            size: ROW_BYTES as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        viewport: &Viewport,
+    ) -> (wgpu::Texture, wgpu::TextureView, wgpu::TextureView) {
+        // One pixel is all a pick ever looks at
+        let size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let target = |label, format, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+        };
+
+        let texture = target(
+            "id_texture",
+            ID_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        );
+        let depth = target(
+            "id_depth_texture",
+            viewport.depth_config.format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_view = depth.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view, depth_view)
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        viewport: &Viewport,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ID Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Vert::desc(), InstanceRaw::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ID_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            // Same culling as the main pipeline so hidden faces don't win
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: viewport.depth_config.format,
+                depth_write_enabled: true,
+                depth_compare: viewport.depth_config.compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+// Always single sampled and the targets don't follow the window size,
+// only the depth settings need new resources
+impl ViewportDependent for IdBuffer {
+    fn viewport_changed(&mut self, device: &wgpu::Device, viewport: &Viewport) {
+        let depth_changed = viewport.depth_config.format != self.viewport.depth_config.format
+            || viewport.depth_config.compare != self.viewport.depth_config.compare;
+        if depth_changed {
+            self.pipeline =
+                Self::create_pipeline(device, &self.pipeline_layout, &self.shader, viewport);
+            (self.texture, self.view, self.depth_view) = Self::create_targets(device, viewport);
+        }
+        self.viewport = *viewport;
+
+        // The pick has to stay inside the new size, or gets dropped when
+        // there's nothing left of it
+        if let Some(Readback::Requested { x, y }) = self.readback {
+            self.readback = None;
+            self.request(x, y);
+        }
+    }
+}
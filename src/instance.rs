@@ -0,0 +1,189 @@
+// Per instance data for drawing one mesh many times in a single draw call.
+// It comes in through a second vertex buffer that only steps once per
+// instance, every scene pipeline has it at slot 1
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Mat4, Vec4};
+use wgpu::util::DeviceExt;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Instance {
+    // Relative to the object's own transform
+    pub transform: Mat4,
+    // Multiplied into the vertex color
+    pub tint: Vec4,
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            transform: Mat4::IDENTITY,
+            tint: Vec4::ONE,
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(transform: Mat4) -> Self {
+        Self {
+            transform,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_tint(mut self, tint: Vec4) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn to_raw(self) -> InstanceRaw {
+        InstanceRaw {
+            model: self.transform.to_cols_array_2d(),
+            normal: Mat3::from_mat4(self.transform)
+                .inverse()
+                .transpose()
+                .to_cols_array_2d(),
+            tint: self.tint.to_array(),
+        }
+    }
+}
+
+// What actually goes into the buffer, matches InstanceInput in common.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct InstanceRaw {
+    pub model: [[f32; 4]; 4],
+    // Inverse transpose like ObjectUniform::normal, a 3x3 is enough here
+    pub normal: [[f32; 3]; 3],
+    pub tint: [f32; 4],
+}
+
+impl InstanceRaw {
+    // Locations carry on after the ones Vert::desc() uses
+    const ATTRIBUTES: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+        // Model matrix, one column per location
+        4 => Float32x4,
+        5 => Float32x4,
+        6 => Float32x4,
+        7 => Float32x4,
+        // Normal matrix
+        8 => Float32x3,
+        9 => Float32x3,
+        10 => Float32x3,
+        // Tint
+        11 => Float32x4,
+    ];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// The instances of one object and the GPU copy of them. Changes only get
+// uploaded by `update`, the buffer grows as needed but never shrinks
+pub struct InstanceBuffer {
+    instances: Vec<Instance>,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    dirty: bool,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        let capacity = instances.len().max(1);
+        Self {
+            buffer: Self::create_buffer(device, &instances, capacity),
+            instances,
+            capacity,
+            dirty: false,
+        }
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn len(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    // Returns the index of the new instance
+    pub fn push(&mut self, instance: Instance) -> usize {
+        self.instances.push(instance);
+        self.dirty = true;
+        self.instances.len() - 1
+    }
+
+    // False when there's no instance at `index`
+    pub fn set(&mut self, index: usize, instance: Instance) -> bool {
+        let Some(slot) = self.instances.get_mut(index) else {
+            return false;
+        };
+        *slot = instance;
+        self.dirty = true;
+        true
+    }
+
+    // Shifts the indices of every instance after it
+    pub fn remove(&mut self, index: usize) -> Instance {
+        self.dirty = true;
+        self.instances.remove(index)
+    }
+
+    pub fn replace(&mut self, instances: Vec<Instance>) {
+        self.instances = instances;
+        self.dirty = true;
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        if self.instances.len() > self.capacity {
+            // Doubling keeps a slowly growing set from reallocating every update
+            self.capacity = self.instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, &self.instances, self.capacity);
+            return;
+        }
+
+        let raw: Vec<_> = self
+            .instances
+            .iter()
+            .map(|instance| instance.to_raw())
+            .collect();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+    }
+
+    // Nothing to bind when there are no instances, a buffer slice can't be empty
+    pub fn slice(&self) -> Option<wgpu::BufferSlice<'_>> {
+        let size = (self.instances.len() * std::mem::size_of::<InstanceRaw>()) as u64;
+        (size > 0).then(|| self.buffer.slice(..size))
+    }
+
+    // Just the one instance, for drawing it on its own. Offsetting the
+    // buffer works where a non-zero first instance isn't supported (WebGL2)
+    pub fn instance_slice(&self, index: usize) -> Option<wgpu::BufferSlice<'_>> {
+        let stride = std::mem::size_of::<InstanceRaw>() as u64;
+        let start = index as u64 * stride;
+        (index < self.instances.len()).then(|| self.buffer.slice(start..start + stride))
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        instances: &[Instance],
+        capacity: usize,
+    ) -> wgpu::Buffer {
+        let mut raw: Vec<_> = instances.iter().map(|instance| instance.to_raw()).collect();
+        raw.resize(capacity, InstanceRaw::zeroed());
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance buffer"),
+            contents: bytemuck::cast_slice(&raw),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+}
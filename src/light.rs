@@ -0,0 +1,310 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+use crate::shadow::ShadowMap;
+
+// Injected into the shaders by the preprocessor, lights live in a uniform
+// buffer since WebGL2 doesn't have storage buffers
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    Directional {
+        direction: Vec3,
+    },
+    Point {
+        position: Vec3,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        // Cone angles in radians, light fades out between the two
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+    // Constant, linear and quadratic falloff, unused for directional lights
+    pub attenuation: Vec3,
+}
+
+impl Light {
+    // Falloff that reaches roughly zero around 50 units
+    pub const DEFAULT_ATTENUATION: Vec3 = Vec3::new(1.0, 0.09, 0.032);
+
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            color,
+            intensity,
+            attenuation: Vec3::X,
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Point { position },
+            color,
+            intensity,
+            attenuation: Self::DEFAULT_ATTENUATION,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+        color: Vec3,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                position,
+                direction: direction.normalize(),
+                inner_angle,
+                outer_angle: outer_angle.max(inner_angle),
+            },
+            color,
+            intensity,
+            attenuation: Self::DEFAULT_ATTENUATION,
+        }
+    }
+
+    // Falloff for point and spot lights, 1 / (constant + linear * d + quadratic * d^2)
+    #[allow(dead_code)]
+    pub fn with_attenuation(mut self, constant: f32, linear: f32, quadratic: f32) -> Self {
+        self.attenuation = Vec3::new(constant, linear, quadratic);
+        self
+    }
+
+    fn to_raw(self) -> LightRaw {
+        let (kind, position, direction, cone) = match self.kind {
+            LightKind::Directional { direction } => (0, Vec3::ZERO, direction, [1.0, 1.0]),
+            LightKind::Point { position } => (1, position, Vec3::ZERO, [1.0, 1.0]),
+            LightKind::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+            } => (
+                2,
+                position,
+                direction,
+                [inner_angle.cos(), outer_angle.cos()],
+            ),
+        };
+
+        LightRaw {
+            position: position.extend(kind as f32).to_array(),
+            direction: direction.extend(0.0).to_array(),
+            color: self.color.extend(self.intensity).to_array(),
+            attenuation: self.attenuation.extend(0.0).to_array(),
+            cone: [cone[0], cone[1], 0.0, 0.0],
+        }
+    }
+}
+
+// GPU layout of a single light, everything is a vec4 to keep std140 happy
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct LightRaw {
+    position: [f32; 4],    // xyz position, w light kind
+    direction: [f32; 4],   // xyz direction
+    color: [f32; 4],       // rgb color, a intensity
+    attenuation: [f32; 4], // constant, linear, quadratic
+    cone: [f32; 4],        // cos of inner and outer angle
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct LightUniform {
+    lights: [LightRaw; MAX_LIGHTS],
+    ambient: [f32; 4],
+    count: [u32; 4],
+}
+
+impl LightUniform {
+    // The shadow map shares this group, WebGL2 only gives us 4 of them
+    pub fn bind_desc<'a>() -> wgpu::BindGroupLayoutDescriptor<'a> {
+        wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Shadow uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Shadow map
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("light_bind_group_layout"),
+        }
+    }
+}
+
+// All the lights in the scene plus the buffer they get uploaded to
+pub struct Lights {
+    lights: Vec<Light>,
+    pub ambient: Vec3,
+    pub buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    dirty: bool,
+}
+
+impl Lights {
+    pub fn new(device: &wgpu::Device, shadow_map: &ShadowMap) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light buffer"),
+            contents: bytemuck::cast_slice(&[LightUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&LightUniform::bind_desc());
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer, shadow_map);
+
+        Self {
+            lights: Vec::new(),
+            ambient: Vec3::splat(0.05),
+            buffer,
+            bind_group_layout,
+            bind_group,
+            dirty: true,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        shadow_map: &ShadowMap,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadow_map.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadow_map.texture.sampler),
+                },
+            ],
+            label: Some("light_bind_group"),
+        })
+    }
+
+    // Needed whenever the shadow map texture gets recreated
+    pub fn rebind_shadow_map(&mut self, device: &wgpu::Device, shadow_map: &ShadowMap) {
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.buffer, shadow_map);
+    }
+
+    // The first directional light is the one that casts shadows
+    pub fn shadow_caster(&self) -> Option<(usize, Vec3)> {
+        self.lights
+            .iter()
+            .enumerate()
+            .find_map(|(index, light)| match light.kind {
+                LightKind::Directional { direction } => Some((index, direction)),
+                _ => None,
+            })
+    }
+
+    // Returns None once MAX_LIGHTS is reached
+    pub fn add(&mut self, light: Light) -> Option<usize> {
+        if self.lights.len() >= MAX_LIGHTS {
+            log::warn!("Light limit of {MAX_LIGHTS} reached");
+            return None;
+        }
+
+        self.lights.push(light);
+        self.dirty = true;
+        Some(self.lights.len() - 1)
+    }
+
+    // Shifts the indices of every light after it
+    #[allow(dead_code)]
+    pub fn remove(&mut self, index: usize) -> Option<Light> {
+        (index < self.lights.len()).then(|| {
+            self.dirty = true;
+            self.lights.remove(index)
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Light> {
+        self.dirty = true;
+        self.lights.get_mut(index)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_ambient(&mut self, ambient: Vec3) {
+        self.ambient = ambient;
+        self.dirty = true;
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+
+        let mut uniform = LightUniform::zeroed();
+        for (raw, light) in uniform.lights.iter_mut().zip(&self.lights) {
+            *raw = light.to_raw();
+        }
+        uniform.ambient = self.ambient.extend(1.0).to_array();
+        uniform.count[0] = self.lights.len() as u32;
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        self.dirty = false;
+    }
+}
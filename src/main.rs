@@ -3,10 +3,29 @@ use winit::event_loop::{ControlFlow, EventLoop};
 // Modules
 mod app;
 mod camera;
+mod hdr;
+#[cfg(not(target_arch = "wasm32"))]
+mod hot_reload;
+mod id_buffer;
+mod instance;
+mod light;
+mod material;
 mod model;
+mod obj;
+mod outline;
+mod picking;
+mod preprocessor;
+mod reflect;
+mod render_mode;
+mod scene;
+mod scene_graph;
+mod shadow;
+mod skybox;
 mod state;
 mod texture;
+mod timing;
 mod vert;
+mod viewport;
 
 use crate::app::App;
 
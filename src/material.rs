@@ -0,0 +1,248 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+use crate::model::GltfMaterial;
+use crate::obj::MtlMaterial;
+use crate::texture::Texture;
+
+// Factors from glTF's metallic-roughness model, each one
+// gets multiplied with its texture (if there is one)
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialParams {
+    pub base_color: Vec4,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub emissive: Vec3,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::ONE,
+            metallic: 0.0,
+            roughness: 0.5,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive: Vec3::ZERO,
+        }
+    }
+}
+
+impl MaterialParams {
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn from_gltf(material: &GltfMaterial) -> Self {
+        Self {
+            base_color: material.base_color_factor,
+            metallic: material.metallic_factor,
+            roughness: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            emissive: material.emissive_factor,
+        }
+    }
+
+    // Rough conversion from the Phong style values in .mtl files. The
+    // diffuse color already went into the vertex colors so it's left out
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn from_mtl(material: &MtlMaterial) -> Self {
+        Self {
+            roughness: (2.0 / (material.shininess + 2.0)).sqrt(),
+            emissive: material.emissive,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    // metallic, roughness, normal scale, occlusion strength
+    factors: [f32; 4],
+}
+
+impl From<&MaterialParams> for MaterialUniform {
+    fn from(params: &MaterialParams) -> Self {
+        Self {
+            base_color: params.base_color.to_array(),
+            emissive: params.emissive.extend(0.0).to_array(),
+            factors: [
+                params.metallic,
+                params.roughness.clamp(0.04, 1.0),
+                params.normal_scale,
+                params.occlusion_strength,
+            ],
+        }
+    }
+}
+
+// Texture maps for a material, anything left as None
+// gets a neutral 1x1 texture instead
+#[derive(Default)]
+pub struct MaterialTextures<'a> {
+    pub base_color: Option<&'a Texture>,
+    pub metallic_roughness: Option<&'a Texture>,
+    pub normal: Option<&'a Texture>,
+    pub occlusion: Option<&'a Texture>,
+    pub emissive: Option<&'a Texture>,
+}
+
+pub struct Material {
+    pub params: MaterialParams,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Material {
+    // Call after changing `params` to push them to the GPU
+    #[allow(dead_code)]
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniform::from(&self.params)]),
+        );
+    }
+}
+
+// Bind group layout shared by every material plus the fallback textures
+pub struct MaterialLayout {
+    pub layout: wgpu::BindGroupLayout,
+    white: Texture,
+    flat_normal: Texture,
+}
+
+impl MaterialLayout {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let white = Texture::from_color(
+            device,
+            queue,
+            [255, 255, 255, 255],
+            wgpu::TextureFormat::Rgba8Unorm,
+            Some("white_texture"),
+        );
+        let flat_normal = Texture::from_color(
+            device,
+            queue,
+            [128, 128, 255, 255],
+            wgpu::TextureFormat::Rgba8Unorm,
+            Some("flat_normal_texture"),
+        );
+
+        Self {
+            layout: device.create_bind_group_layout(&Self::bind_desc()),
+            white,
+            flat_normal,
+        }
+    }
+
+    pub fn bind_desc<'a>() -> wgpu::BindGroupLayoutDescriptor<'a> {
+        const fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }
+        }
+
+        const ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1), // Base color
+            texture_entry(2), // Metallic (b) roughness (g)
+            texture_entry(3), // Normal
+            texture_entry(4), // Occlusion (r)
+            texture_entry(5), // Emissive
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        wgpu::BindGroupLayoutDescriptor {
+            entries: ENTRIES,
+            label: Some("material_bind_group_layout"),
+        }
+    }
+
+    pub fn create(
+        &self,
+        device: &wgpu::Device,
+        params: MaterialParams,
+        textures: MaterialTextures,
+        label: Option<&str>,
+    ) -> Material {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(&params)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let base_color = textures.base_color.unwrap_or(&self.white);
+        let metallic_roughness = textures.metallic_roughness.unwrap_or(&self.white);
+        let normal = textures.normal.unwrap_or(&self.flat_normal);
+        let occlusion = textures.occlusion.unwrap_or(&self.white);
+        let emissive = textures.emissive.unwrap_or(&self.white);
+
+        // All maps share the sampler of the base color texture
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&base_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&metallic_roughness.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&occlusion.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&emissive.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&base_color.sampler),
+                },
+            ],
+            label,
+        });
+
+        Material {
+            params,
+            buffer,
+            bind_group,
+        }
+    }
+}
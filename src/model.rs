@@ -1,16 +1,26 @@
-use glam::{Vec2, Vec4};
+use std::collections::HashMap;
+use std::fmt;
 
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use image::{DynamicImage, ImageBuffer};
+
+use crate::obj::{MeshGroup, MtlMaterial};
+use crate::texture::Texture;
 use crate::vert::Vert;
 
+#[derive(Debug)]
 pub struct Model {
     pub verts: Vec<Vert>,
-    pub indicies: Vec<u16>,
+    pub indicies: Vec<u32>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub groups: Vec<MeshGroup>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub materials: Vec<MtlMaterial>,
 }
 
 const BASE_COLOR: Vec4 = Vec4::new(1.0, 1.0, 1.0, 1.0);
 
 impl Model {
-    #[allow(dead_code)]
     pub fn square(size: f32) -> Self {
         let size = size.clamp(-1., 1.);
 
@@ -38,11 +48,17 @@ impl Model {
         ];
 
         // &[0, 1, 3, 1, 2, 3] clockwise order
-        let indicies: Vec<u16> = vec![3, 2, 1, 3, 1, 0];
+        let indicies: Vec<u32> = vec![3, 2, 1, 3, 1, 0];
 
-        Self { verts, indicies }
+        let mut model = Self {
+            verts,
+            indicies,
+            groups: Vec::new(),
+            materials: Vec::new(),
+        };
+        model.generate_normals();
+        model
     }
-    #[allow(dead_code)]
     pub fn cube(size: f32) -> Self {
         let size = size.clamp(-1., 1.); // Clamp to NDC coordinates
 
@@ -175,7 +191,7 @@ impl Model {
             ),
         ];
 
-        let indicies: Vec<u16> = vec![
+        let indicies: Vec<u32> = vec![
             0, 1, 2, 2, 3, 0, // top
             4, 5, 6, 6, 7, 4, // bottom
             8, 9, 10, 10, 11, 8, // right
@@ -184,6 +200,587 @@ impl Model {
             20, 21, 22, 22, 23, 20, // back
         ];
 
-        Self { verts, indicies }
+        let mut model = Self {
+            verts,
+            indicies,
+            groups: Vec::new(),
+            materials: Vec::new(),
+        };
+        model.generate_normals();
+        model
+    }
+
+    // Copy of just the triangles in `group`, with the verts they don't
+    // use left out
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn group(&self, group: &MeshGroup) -> Model {
+        let mut remap = HashMap::new();
+        let mut verts = Vec::new();
+        let indicies = self.indicies[group.indices.start as usize..group.indices.end as usize]
+            .iter()
+            .map(|&index| {
+                *remap.entry(index).or_insert_with(|| {
+                    verts.push(self.verts[index as usize]);
+                    verts.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        Model {
+            verts,
+            indicies,
+            groups: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    // Smooth normals weighted by triangle area, verts that aren't
+    // shared between faces (like the cube's) end up with flat normals
+    pub fn generate_normals(&mut self) {
+        let normals = self.face_normals();
+        for (vert, normal) in self.verts.iter_mut().zip(normals) {
+            vert.normal = normal.normalize_or_zero().to_array();
+        }
+    }
+
+    // Same as generate_normals but leaves verts that already have one alone,
+    // `missing` has one entry per vert
+    pub fn generate_missing_normals(&mut self, missing: &[bool]) {
+        let normals = self.face_normals();
+        for ((vert, normal), missing) in self.verts.iter_mut().zip(normals).zip(missing) {
+            if *missing {
+                vert.normal = normal.normalize_or_zero().to_array();
+            }
+        }
+    }
+
+    // Sum of the (unnormalized) normals of every triangle touching each vert
+    fn face_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.verts.len()];
+
+        for tri in self.indicies.chunks_exact(3) {
+            let [a, b, c] = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+            let pa = Vec4::from_array(self.verts[a].pos).truncate();
+            let pb = Vec4::from_array(self.verts[b].pos).truncate();
+            let pc = Vec4::from_array(self.verts[c].pos).truncate();
+
+            // Counter clockwise winding is our front face
+            let face_normal = (pb - pa).cross(pc - pa);
+            normals[a] += face_normal;
+            normals[b] += face_normal;
+            normals[c] += face_normal;
+        }
+
+        normals
+    }
+}
+
+// Errors that can come out of the glTF importer
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(gltf::Error),
+    Texture(wgpu::Error),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Gltf(err) => write!(f, "glTF error: {err}"),
+            GltfError::Texture(err) => write!(f, "texture error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Gltf(err) => Some(err),
+            GltfError::Texture(err) => Some(err),
+        }
+    }
+}
+
+impl From<gltf::Error> for GltfError {
+    fn from(err: gltf::Error) -> Self {
+        GltfError::Gltf(err)
+    }
+}
+
+// Material for a glTF primitive, texture indices point into GltfScene::textures
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: Vec4,
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<usize>,
+}
+
+// Node in the glTF hierarchy, `world` already has the parent transforms applied
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub local: Mat4,
+    pub world: Mat4,
+}
+
+// One primitive of a glTF mesh, placed by the node that references it
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct GltfModel {
+    pub name: Option<String>,
+    pub model: Model,
+    pub node: usize,
+    pub transform: Mat4,
+    pub material: Option<usize>,
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct GltfScene {
+    pub models: Vec<GltfModel>,
+    pub nodes: Vec<GltfNode>,
+    pub materials: Vec<GltfMaterial>,
+    pub textures: Vec<Texture>,
+}
+
+impl GltfScene {
+    // Imports a .gltf or .glb file, external buffers and images are resolved relative to it
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open<P: AsRef<std::path::Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+    ) -> Result<Self, GltfError> {
+        let (document, buffers, images) = gltf::import(path)?;
+        Self::from_import(device, queue, &document, &buffers, &images)
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    fn from_import(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> Result<Self, GltfError> {
+        // The same image can be used as color and as data, so
+        // textures are cached per image and format. Images we can't
+        // decode are None, the material falls back to its default texture
+        let mut textures: Vec<Texture> = Vec::new();
+        let mut texture_lookup: HashMap<(usize, bool), Option<usize>> = HashMap::new();
+        let mut load_texture = |texture: gltf::Texture,
+                                srgb: bool|
+         -> Result<Option<usize>, GltfError> {
+            let image_index = texture.source().index();
+            if let Some(index) = texture_lookup.get(&(image_index, srgb)) {
+                return Ok(*index);
+            }
+
+            let Some(img) = gltf_image_to_dynamic(&images[image_index]) else {
+                log::warn!(
+                    "glTF image {image_index} has unsupported format {:?}, using the default texture",
+                    images[image_index].format
+                );
+                texture_lookup.insert((image_index, srgb), None);
+                return Ok(None);
+            };
+
+            let format = if srgb {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            } else {
+                wgpu::TextureFormat::Rgba8Unorm
+            };
+            let texture = Texture::from_image_format(
+                device,
+                queue,
+                &img,
+                format,
+                texture.name().or(Some("gltf_texture")),
+            )
+            .map_err(GltfError::Texture)?;
+
+            textures.push(texture);
+            let index = Some(textures.len() - 1);
+            texture_lookup.insert((image_index, srgb), index);
+            Ok(index)
+        };
+
+        let mut materials: Vec<GltfMaterial> = Vec::new();
+        for material in document.materials() {
+            let pbr = material.pbr_metallic_roughness();
+
+            // Color textures are sRGB, everything else is linear data
+            let base_color_texture = pbr
+                .base_color_texture()
+                .map(|info| load_texture(info.texture(), true))
+                .transpose()?
+                .flatten();
+            let metallic_roughness_texture = pbr
+                .metallic_roughness_texture()
+                .map(|info| load_texture(info.texture(), false))
+                .transpose()?
+                .flatten();
+            let normal = material.normal_texture();
+            let normal_texture = normal
+                .as_ref()
+                .map(|info| load_texture(info.texture(), false))
+                .transpose()?
+                .flatten();
+            let occlusion = material.occlusion_texture();
+            let occlusion_texture = occlusion
+                .as_ref()
+                .map(|info| load_texture(info.texture(), false))
+                .transpose()?
+                .flatten();
+            let emissive_texture = material
+                .emissive_texture()
+                .map(|info| load_texture(info.texture(), true))
+                .transpose()?
+                .flatten();
+
+            materials.push(GltfMaterial {
+                name: material.name().map(str::to_string),
+                base_color_factor: Vec4::from_array(pbr.base_color_factor()),
+                base_color_texture,
+                metallic_factor: pbr.metallic_factor(),
+                roughness_factor: pbr.roughness_factor(),
+                metallic_roughness_texture,
+                normal_texture,
+                normal_scale: normal.map(|n| n.scale()).unwrap_or(1.0),
+                occlusion_texture,
+                occlusion_strength: occlusion.map(|o| o.strength()).unwrap_or(1.0),
+                emissive_factor: Vec3::from_array(material.emissive_factor()),
+                emissive_texture,
+            });
+        }
+
+        // Local transforms and parent links for every node
+        let mut nodes: Vec<GltfNode> = document
+            .nodes()
+            .map(|node| GltfNode {
+                name: node.name().map(str::to_string),
+                parent: None,
+                children: node.children().map(|child| child.index()).collect(),
+                local: Mat4::from_cols_array_2d(&node.transform().matrix()),
+                world: Mat4::IDENTITY,
+            })
+            .collect();
+
+        for index in 0..nodes.len() {
+            for child in nodes[index].children.clone() {
+                nodes[child].parent = Some(index);
+            }
+        }
+
+        // Walk down from the scene roots so parents are resolved before children
+        let roots: Vec<usize> = match document.default_scene().or(document.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len())
+                .filter(|index| nodes[*index].parent.is_none())
+                .collect(),
+        };
+
+        let mut stack: Vec<(usize, Mat4)> = roots
+            .iter()
+            .rev()
+            .map(|root| (*root, Mat4::IDENTITY))
+            .collect();
+        let mut visited: Vec<usize> = Vec::new();
+
+        while let Some((index, parent_world)) = stack.pop() {
+            let world = parent_world * nodes[index].local;
+            nodes[index].world = world;
+            visited.push(index);
+
+            for child in nodes[index].children.iter().rev() {
+                stack.push((*child, world));
+            }
+        }
+
+        // Turn every triangle primitive reachable from the scene into a Model
+        let document_nodes: Vec<gltf::Node> = document.nodes().collect();
+        let mut models: Vec<GltfModel> = Vec::new();
+        for index in visited {
+            let node = &document_nodes[index];
+            let Some(mesh) = node.mesh() else {
+                continue;
+            };
+
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!(
+                        "Skipping primitive {} of mesh {:?}, mode {:?} is not supported",
+                        primitive.index(),
+                        mesh.name(),
+                        primitive.mode()
+                    );
+                    continue;
+                }
+
+                let model = match Model::from_gltf_primitive(&primitive, buffers) {
+                    Ok(model) => model,
+                    Err(reason) => {
+                        log::warn!(
+                            "Skipping primitive {} of mesh {:?}, {reason}",
+                            primitive.index(),
+                            mesh.name()
+                        );
+                        continue;
+                    }
+                };
+
+                models.push(GltfModel {
+                    name: mesh.name().map(str::to_string),
+                    model,
+                    node: index,
+                    transform: nodes[index].world,
+                    material: primitive.material().index(),
+                });
+            }
+        }
+
+        Ok(Self {
+            models,
+            nodes,
+            materials,
+            textures,
+        })
+    }
+}
+
+impl Model {
+    // Errors say why the primitive can't be used, malformed
+    // files get skipped instead of indexing out of bounds
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    fn from_gltf_primitive(
+        primitive: &gltf::Primitive,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Self, String> {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .ok_or("it has no positions")?
+            .collect();
+        let uv_set = gltf_uv_set(&primitive.material());
+        let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(uv_set) {
+            Some(uvs) => uvs.into_f32().collect(),
+            None => vec![[0.0, 0.0]; positions.len()],
+        };
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+        let colors: Vec<[f32; 4]> = match reader.read_colors(0) {
+            Some(colors) => colors.into_rgba_f32().collect(),
+            None => vec![BASE_COLOR.to_array(); positions.len()],
+        };
+
+        let counts = [
+            ("texture coordinates", tex_coords.len()),
+            (
+                "normals",
+                normals.as_ref().map_or(positions.len(), Vec::len),
+            ),
+            ("colors", colors.len()),
+        ];
+        for (attribute, count) in counts {
+            if count != positions.len() {
+                return Err(format!(
+                    "it has {count} {attribute} for {} positions",
+                    positions.len()
+                ));
+            }
+        }
+
+        let verts = positions
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| {
+                let vert = Vert::new(
+                    Vec3::from_array(*pos).extend(1.0),
+                    Vec4::from_array(colors[i]),
+                    tex_coords[i],
+                );
+                match &normals {
+                    Some(normals) => vert.with_normal(normals[i]),
+                    None => vert,
+                }
+            })
+            .collect();
+
+        let indicies: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if let Some(index) = indicies.iter().find(|&&i| i as usize >= positions.len()) {
+            return Err(format!(
+                "index {index} is out of range for {} positions",
+                positions.len()
+            ));
+        }
+
+        let mut model = Self {
+            verts,
+            indicies,
+            groups: Vec::new(),
+            materials: Vec::new(),
+        };
+        if normals.is_none() {
+            model.generate_normals();
+        }
+        Ok(model)
+    }
+}
+
+// Verts only carry one set of UVs, so use the one the material's textures
+// ask for. Base color wins if they don't agree
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn gltf_uv_set(material: &gltf::Material) -> u32 {
+    let pbr = material.pbr_metallic_roughness();
+    let mut sets = [
+        pbr.base_color_texture().map(|info| info.tex_coord()),
+        pbr.metallic_roughness_texture()
+            .map(|info| info.tex_coord()),
+        material.normal_texture().map(|info| info.tex_coord()),
+        material.occlusion_texture().map(|info| info.tex_coord()),
+        material.emissive_texture().map(|info| info.tex_coord()),
+    ]
+    .into_iter()
+    .flatten();
+
+    let set = sets.next().unwrap_or(0);
+    if sets.any(|other| other != set) {
+        log::warn!(
+            "Material {:?} uses more than one UV set, only set {set} is loaded",
+            material.name()
+        );
+    }
+    set
+}
+
+// gltf decodes images with its own copy of the image crate,
+// so rebuild them as one of ours. None for formats we have no match for
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn gltf_image_to_dynamic(data: &gltf::image::Data) -> Option<DynamicImage> {
+    use gltf::image::Format;
+
+    let (w, h) = (data.width, data.height);
+    let pixels = data.pixels.clone();
+    let pixels_16 = || -> Vec<u16> {
+        data.pixels
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect()
+    };
+
+    match data.format {
+        Format::R8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgba8),
+        Format::R16 => ImageBuffer::from_raw(w, h, pixels_16()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => ImageBuffer::from_raw(w, h, pixels_16()).map(DynamicImage::ImageLumaA16),
+        Format::R16G16B16 => ImageBuffer::from_raw(w, h, pixels_16()).map(DynamicImage::ImageRgb16),
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(w, h, pixels_16()).map(DynamicImage::ImageRgba16)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material(json: &str) -> gltf::Gltf {
+        let doc = format!(
+            r#"{{"asset": {{"version": "2.0"}},
+                "images": [{{"uri": "a.png"}}],
+                "textures": [{{"source": 0}}],
+                "materials": [{json}]}}"#
+        );
+        gltf::Gltf::from_slice(doc.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn uv_set_follows_the_base_color_texture() {
+        let gltf = material(
+            r#"{"pbrMetallicRoughness": {"baseColorTexture": {"index": 0, "texCoord": 1}},
+                "normalTexture": {"index": 0, "texCoord": 0}}"#,
+        );
+        assert_eq!(gltf_uv_set(&gltf.materials().next().unwrap()), 1);
+    }
+
+    #[test]
+    fn uv_set_defaults_to_zero() {
+        let gltf = material("{}");
+        assert_eq!(gltf_uv_set(&gltf.materials().next().unwrap()), 0);
+
+        let gltf = material(r#"{"emissiveTexture": {"index": 0, "texCoord": 2}}"#);
+        assert_eq!(gltf_uv_set(&gltf.materials().next().unwrap()), 2);
+    }
+
+    // One triangle whose UV accessor has `uv_count` entries
+    fn triangle(uv_count: usize) -> (gltf::Gltf, Vec<gltf::buffer::Data>) {
+        let mut bytes: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        bytes.extend(std::iter::repeat_n(0, uv_count * 8));
+        let doc = format!(
+            r#"{{"asset": {{"version": "2.0"}},
+                "buffers": [{{"uri": "a.bin", "byteLength": {len}}}],
+                "bufferViews": [{{"buffer": 0, "byteLength": 36}},
+                                {{"buffer": 0, "byteOffset": 36, "byteLength": {uv_len}}}],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 1, "componentType": 5126, "count": {uv_count}, "type": "VEC2"}}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0, "TEXCOORD_0": 1}}}}]}}]}}"#,
+            len = bytes.len(),
+            uv_len = uv_count * 8,
+        );
+        let gltf = gltf::Gltf::from_slice(doc.as_bytes()).unwrap();
+        (gltf, vec![gltf::buffer::Data(bytes)])
+    }
+
+    #[test]
+    fn mismatched_attribute_counts_are_rejected() {
+        let (gltf, buffers) = triangle(3);
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        let model = Model::from_gltf_primitive(&primitive, &buffers).unwrap();
+        assert_eq!(model.verts.len(), 3);
+
+        let (gltf, buffers) = triangle(2);
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        assert!(Model::from_gltf_primitive(&primitive, &buffers).is_err());
+    }
+
+    #[test]
+    fn unsupported_images_are_skipped() {
+        let data = gltf::image::Data {
+            pixels: vec![0; 4 * 4 * 4],
+            format: gltf::image::Format::R32G32B32A32FLOAT,
+            width: 2,
+            height: 2,
+        };
+        assert!(gltf_image_to_dynamic(&data).is_none());
+
+        let data = gltf::image::Data {
+            pixels: vec![255; 2 * 2 * 4],
+            format: gltf::image::Format::R8G8B8A8,
+            width: 2,
+            height: 2,
+        };
+        assert!(gltf_image_to_dynamic(&data).is_some());
     }
 }
@@ -0,0 +1,667 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::model::Model;
+use crate::vert::Vert;
+
+// Errors that can come out of the OBJ/MTL parser
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, reason: String },
+    Mtl { file: String, source: Box<ObjError> },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "I/O error: {err}"),
+            ObjError::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+            ObjError::Mtl { file, source } => write!(f, "in material library {file}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io(err) => Some(err),
+            ObjError::Mtl { source, .. } => Some(source.as_ref()),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(err: io::Error) -> Self {
+        ObjError::Io(err)
+    }
+}
+
+// Material read from a .mtl file, texture maps are kept as paths
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub emissive: Vec3,
+    pub shininess: f32,
+    pub dissolve: f32,
+    pub illum: u32,
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub dissolve_map: Option<String>,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ambient: Vec3::ZERO,
+            diffuse: Vec3::ONE,
+            specular: Vec3::ZERO,
+            emissive: Vec3::ZERO,
+            shininess: 0.0,
+            dissolve: 1.0,
+            illum: 0,
+            diffuse_map: None,
+            specular_map: None,
+            normal_map: None,
+            dissolve_map: None,
+        }
+    }
+
+    // Vertex color used for faces that have this material applied
+    pub fn color(&self) -> Vec4 {
+        self.diffuse.extend(self.dissolve)
+    }
+}
+
+// Run of indices that belong to the same group and material
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct MeshGroup {
+    pub name: String,
+    pub material: Option<usize>,
+    pub indices: Range<u32>,
+}
+
+pub fn parse_mtl<R: BufRead>(reader: R) -> Result<Vec<MtlMaterial>, ObjError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = i + 1;
+        let mut tokens = Tokens::new(&line, line_no);
+
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            let name = tokens.rest()?;
+            materials.push(MtlMaterial::new(name));
+            continue;
+        }
+
+        let Some(mat) = materials.last_mut() else {
+            return Err(parse_err(
+                line_no,
+                format!("'{keyword}' before any 'newmtl' statement"),
+            ));
+        };
+
+        match keyword {
+            "Ka" => mat.ambient = tokens.vec3()?,
+            "Kd" => mat.diffuse = tokens.vec3()?,
+            "Ks" => mat.specular = tokens.vec3()?,
+            "Ke" => mat.emissive = tokens.vec3()?,
+            "Ns" => mat.shininess = tokens.float()?,
+            "d" => mat.dissolve = tokens.float()?,
+            "Tr" => mat.dissolve = 1.0 - tokens.float()?,
+            "illum" => mat.illum = tokens.uint()?,
+            "map_Kd" => mat.diffuse_map = Some(tokens.map_path()?),
+            "map_Ks" => mat.specular_map = Some(tokens.map_path()?),
+            "map_Bump" | "map_bump" | "bump" | "norm" => mat.normal_map = Some(tokens.map_path()?),
+            "map_d" => mat.dissolve_map = Some(tokens.map_path()?),
+            // Everything else (Ni, Tf, map_Ka, ...) is not used by us
+            _ => log::debug!("mtl line {line_no}: ignoring '{keyword}'"),
+        }
+    }
+
+    Ok(materials)
+}
+
+impl Model {
+    // Loads an OBJ file, any 'mtllib' statements are ignored
+    pub fn from_obj<R: BufRead>(reader: R) -> Result<Self, ObjError> {
+        Self::from_obj_with_mtl(reader, |name| {
+            log::warn!("Skipping material library {name}, no loader given");
+            Ok(None::<io::Empty>)
+        })
+    }
+
+    // Loads an OBJ file, 'mtllib' names are handed to `load_mtl`
+    // which may return None to skip that library. Libraries that
+    // aren't found are skipped too, their faces use no material
+    pub fn from_obj_with_mtl<R, M, F>(reader: R, mut load_mtl: F) -> Result<Self, ObjError>
+    where
+        R: BufRead,
+        M: BufRead,
+        F: FnMut(&str) -> io::Result<Option<M>>,
+    {
+        let mut positions: Vec<Vec4> = Vec::new();
+        let mut colors: Vec<Option<Vec4>> = Vec::new();
+        let mut tex_coords: Vec<Vec2> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+
+        let mut materials: Vec<MtlMaterial> = Vec::new();
+        let mut current_material: Option<usize> = None;
+        let mut current_group = String::from("default");
+
+        let mut verts: Vec<Vert> = Vec::new();
+        // Verts whose face entry had no normal, these get generated ones
+        let mut missing_normals: Vec<bool> = Vec::new();
+        let mut indicies: Vec<u32> = Vec::new();
+        let mut groups: Vec<MeshGroup> = Vec::new();
+        let mut group_start: u32 = 0;
+
+        // OBJ indexes attributes separately so each unique
+        // combination turns into one of our verts
+        let mut vert_lookup: HashMap<FaceVert, u32> = HashMap::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_no = i + 1;
+            let mut tokens = Tokens::new(&line, line_no);
+
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            match keyword {
+                "v" => {
+                    // Either x y z [w] or x y z r g b
+                    let values = tokens.floats()?;
+                    match values.len() {
+                        3 | 4 => {
+                            let w = values.get(3).copied().unwrap_or(1.0);
+                            positions.push(Vec4::new(values[0], values[1], values[2], w));
+                            colors.push(None);
+                        }
+                        6 => {
+                            positions.push(Vec4::new(values[0], values[1], values[2], 1.0));
+                            colors.push(Some(Vec4::new(values[3], values[4], values[5], 1.0)));
+                        }
+                        n => {
+                            return Err(parse_err(
+                                line_no,
+                                format!("vertex needs 3, 4 or 6 values, got {n}"),
+                            ));
+                        }
+                    }
+                }
+                "vt" => {
+                    let values = tokens.floats()?;
+                    if values.is_empty() || values.len() > 3 {
+                        return Err(parse_err(
+                            line_no,
+                            format!(
+                                "texture coordinate needs 1 to 3 values, got {}",
+                                values.len()
+                            ),
+                        ));
+                    }
+                    // OBJ puts the UV origin in the bottom left, wgpu in the top left
+                    let v = values.get(1).copied().unwrap_or(0.0);
+                    tex_coords.push(Vec2::new(values[0], 1.0 - v));
+                }
+                "vn" => normals.push(tokens.vec3()?),
+                "f" => {
+                    let mut face: Vec<u32> = Vec::new();
+                    for token in tokens.by_ref() {
+                        let face_vert = FaceVert::parse(
+                            token,
+                            line_no,
+                            positions.len(),
+                            tex_coords.len(),
+                            normals.len(),
+                            current_material,
+                        )?;
+
+                        let index = match vert_lookup.get(&face_vert) {
+                            Some(index) => *index,
+                            None => {
+                                let color = colors[face_vert.pos]
+                                    .or_else(|| face_vert.material.map(|m| materials[m].color()))
+                                    .unwrap_or(Vec4::ONE);
+                                let uv = face_vert
+                                    .tex_coord
+                                    .map(|t| tex_coords[t])
+                                    .unwrap_or(Vec2::ZERO);
+
+                                let index = u32::try_from(verts.len()).map_err(|_| {
+                                    parse_err(line_no, "too many vertices".to_string())
+                                })?;
+                                let mut vert = Vert::new(positions[face_vert.pos], color, uv);
+                                if let Some(n) = face_vert.normal {
+                                    vert = vert.with_normal(normals[n]);
+                                }
+                                verts.push(vert);
+                                missing_normals.push(face_vert.normal.is_none());
+                                vert_lookup.insert(face_vert, index);
+                                index
+                            }
+                        };
+                        face.push(index);
+                    }
+
+                    if face.len() < 3 {
+                        return Err(parse_err(
+                            line_no,
+                            format!("face needs at least 3 vertices, got {}", face.len()),
+                        ));
+                    }
+
+                    // Fan triangulation, fine for the convex polygons exporters write
+                    for n in 1..face.len() - 1 {
+                        indicies.extend_from_slice(&[face[0], face[n], face[n + 1]]);
+                    }
+                }
+                "g" | "o" => {
+                    let name = tokens.rest().unwrap_or("default").to_string();
+                    push_group(
+                        &mut groups,
+                        &current_group,
+                        current_material,
+                        &mut group_start,
+                        indicies.len(),
+                    );
+                    current_group = name;
+                }
+                "usemtl" => {
+                    let name = tokens.rest()?;
+                    push_group(
+                        &mut groups,
+                        &current_group,
+                        current_material,
+                        &mut group_start,
+                        indicies.len(),
+                    );
+                    current_material = materials.iter().position(|m| m.name == name);
+                    if current_material.is_none() {
+                        log::warn!("obj line {line_no}: unknown material '{name}'");
+                    }
+                }
+                "mtllib" => {
+                    for file in tokens.by_ref() {
+                        // Exporters often point at libraries that were never
+                        // shipped, the geometry is still worth having
+                        let mtl_reader = match load_mtl(file) {
+                            Ok(Some(reader)) => reader,
+                            Ok(None) => continue,
+                            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                                log::warn!("obj line {line_no}: material library {file} not found");
+                                continue;
+                            }
+                            Err(err) => return Err(err.into()),
+                        };
+                        let mut loaded = parse_mtl(mtl_reader).map_err(|err| ObjError::Mtl {
+                            file: file.to_string(),
+                            source: Box::new(err),
+                        })?;
+                        materials.append(&mut loaded);
+                    }
+                }
+                // Smoothing groups, lines, curves and so on are not supported
+                _ => log::debug!("obj line {line_no}: ignoring '{keyword}'"),
+            }
+        }
+
+        push_group(
+            &mut groups,
+            &current_group,
+            current_material,
+            &mut group_start,
+            indicies.len(),
+        );
+
+        let mut model = Self {
+            verts,
+            indicies,
+            groups,
+            materials,
+        };
+        if missing_normals.iter().any(|missing| *missing) {
+            model.generate_missing_normals(&missing_normals);
+        }
+        Ok(model)
+    }
+}
+
+// Closes off the current group if any faces were added to it
+fn push_group(
+    groups: &mut Vec<MeshGroup>,
+    name: &str,
+    material: Option<usize>,
+    start: &mut u32,
+    end: usize,
+) {
+    let end = end as u32;
+    if end > *start {
+        groups.push(MeshGroup {
+            name: name.to_string(),
+            material,
+            indices: *start..end,
+        });
+    }
+    *start = end;
+}
+
+// Zero based attribute indices for a single face corner
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct FaceVert {
+    pos: usize,
+    tex_coord: Option<usize>,
+    normal: Option<usize>,
+    material: Option<usize>,
+}
+
+impl FaceVert {
+    // Parses v, v/vt, v//vn or v/vt/vn
+    fn parse(
+        token: &str,
+        line: usize,
+        pos_len: usize,
+        tex_len: usize,
+        normal_len: usize,
+        material: Option<usize>,
+    ) -> Result<Self, ObjError> {
+        let mut parts = token.split('/');
+
+        let pos = match parts.next() {
+            Some(p) if !p.is_empty() => resolve_index(p, pos_len, line)?,
+            _ => return Err(parse_err(line, format!("missing position in '{token}'"))),
+        };
+        let tex_coord = match parts.next() {
+            Some(t) if !t.is_empty() => Some(resolve_index(t, tex_len, line)?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(n) if !n.is_empty() => Some(resolve_index(n, normal_len, line)?),
+            _ => None,
+        };
+
+        if parts.next().is_some() {
+            return Err(parse_err(line, format!("malformed face vertex '{token}'")));
+        }
+
+        Ok(Self {
+            pos,
+            tex_coord,
+            normal,
+            material,
+        })
+    }
+}
+
+// OBJ indices start at 1, negative ones count back from the end
+fn resolve_index(token: &str, len: usize, line: usize) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| parse_err(line, format!("invalid index '{token}'")))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        len as i64 + index
+    };
+
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(parse_err(
+            line,
+            format!("index {index} out of range ({len} defined)"),
+        ));
+    }
+
+    Ok(resolved as usize)
+}
+
+fn parse_err(line: usize, reason: String) -> ObjError {
+    ObjError::Parse { line, reason }
+}
+
+// Whitespace tokenizer that knows what line it's on, comments are stripped
+struct Tokens<'a> {
+    line: &'a str,
+    line_no: usize,
+    iter: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str, line_no: usize) -> Self {
+        let line = line.split('#').next().unwrap_or("").trim();
+        Self {
+            line,
+            line_no,
+            iter: line.split_whitespace(),
+        }
+    }
+
+    // Everything after the keyword, used for names that can contain spaces
+    fn rest(&self) -> Result<&'a str, ObjError> {
+        let rest = self
+            .line
+            .split_once(char::is_whitespace)
+            .map(|(_, rest)| rest.trim())
+            .unwrap_or("");
+
+        if rest.is_empty() {
+            Err(parse_err(self.line_no, "missing name".to_string()))
+        } else {
+            Ok(rest)
+        }
+    }
+
+    // Texture map statements can have options before the file name
+    fn map_path(&mut self) -> Result<String, ObjError> {
+        self.iter
+            .by_ref()
+            .last()
+            .map(|s| s.to_string())
+            .ok_or_else(|| parse_err(self.line_no, "missing texture path".to_string()))
+    }
+
+    fn float(&mut self) -> Result<f32, ObjError> {
+        let token = self
+            .iter
+            .next()
+            .ok_or_else(|| parse_err(self.line_no, "expected a number".to_string()))?;
+        token
+            .parse()
+            .map_err(|_| parse_err(self.line_no, format!("invalid number '{token}'")))
+    }
+
+    fn uint(&mut self) -> Result<u32, ObjError> {
+        let token = self
+            .iter
+            .next()
+            .ok_or_else(|| parse_err(self.line_no, "expected an integer".to_string()))?;
+        token
+            .parse()
+            .map_err(|_| parse_err(self.line_no, format!("invalid integer '{token}'")))
+    }
+
+    fn floats(&mut self) -> Result<Vec<f32>, ObjError> {
+        let line_no = self.line_no;
+        self.iter
+            .by_ref()
+            .map(|token| {
+                token
+                    .parse()
+                    .map_err(|_| parse_err(line_no, format!("invalid number '{token}'")))
+            })
+            .collect()
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(self.float()?, self.float()?, self.float()?))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<Model, ObjError> {
+        Model::from_obj(src.as_bytes())
+    }
+
+    #[test]
+    fn quad_is_fan_triangulated() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        assert_eq!(model.verts.len(), 4);
+        assert_eq!(model.indicies, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(model.groups.len(), 1);
+        assert_eq!(model.groups[0].indices, 0..6);
+    }
+
+    #[test]
+    fn groups_split_into_their_own_models() {
+        let model =
+            parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng a\nf 1 2 3\ng b\nf 1 3 4\n").unwrap();
+        assert_eq!(model.groups.len(), 2);
+        let b = model.group(&model.groups[1]);
+        assert_eq!(b.verts.len(), 3);
+        assert_eq!(b.indicies, vec![0, 1, 2]);
+        assert_eq!(b.verts[2].pos, model.verts[3].pos);
+    }
+
+    #[test]
+    fn shared_face_verts_are_reused() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n").unwrap();
+        assert_eq!(model.verts.len(), 4);
+        assert_eq!(model.indicies, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let src =
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25 0.75\nvn 0 0 1\nf -3/-1/-1 -2/-1/-1 -1/-1/-1\n";
+        let model = parse(src).unwrap();
+        assert_eq!(model.verts[1].pos, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(model.verts[2].pos, [0.0, 1.0, 0.0, 1.0]);
+        // V gets flipped for wgpu's top left origin
+        assert_eq!(model.verts[0].tex_coords, [0.25, 0.25]);
+        assert_eq!(model.verts[0].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn missing_normals_are_generated_per_vert() {
+        // The first triangle has a deliberately odd normal that must survive,
+        // the second has none and gets its face normal
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 1 0 0\nf 1//1 2//1 3//1\nf 1 2 3\n";
+        let model = parse(src).unwrap();
+        assert_eq!(model.verts.len(), 6);
+        for vert in &model.verts[..3] {
+            assert_eq!(vert.normal, [1.0, 0.0, 0.0]);
+        }
+        for vert in &model.verts[3..] {
+            assert_eq!(vert.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn usemtl_splits_groups_and_colors_verts() {
+        let obj = "mtllib colors.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                   usemtl red\nf 1 2 3\nusemtl blue\nf 3 2 1\n";
+        let mtl = "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\nd 0.5\n";
+
+        let mut requested = Vec::new();
+        let model = Model::from_obj_with_mtl(obj.as_bytes(), |name| {
+            requested.push(name.to_string());
+            Ok(Some(mtl.as_bytes()))
+        })
+        .unwrap();
+
+        assert_eq!(requested, ["colors.mtl"]);
+        assert_eq!(model.materials.len(), 2);
+        assert_eq!(model.groups.len(), 2);
+        assert_eq!(model.groups[0].material, Some(0));
+        assert_eq!(model.groups[0].indices, 0..3);
+        assert_eq!(model.groups[1].material, Some(1));
+        assert_eq!(model.groups[1].indices, 3..6);
+        assert_eq!(model.verts[0].color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(model.verts[3].color, [0.0, 0.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn missing_material_library_is_skipped() {
+        let obj = "mtllib gone.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+        let model = Model::from_obj_with_mtl(obj.as_bytes(), |_| {
+            Err::<Option<&[u8]>, _>(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .unwrap();
+        assert!(model.materials.is_empty());
+        assert_eq!(model.groups[0].material, None);
+
+        // Anything else still fails the load
+        let failed = Model::from_obj_with_mtl(obj.as_bytes(), |_| {
+            Err::<Option<&[u8]>, _>(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn unknown_material_falls_back_to_white() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl nope\nf 1 2 3\n").unwrap();
+        assert_eq!(model.groups[0].material, None);
+        assert_eq!(model.verts[0].color, [1.0; 4]);
+    }
+
+    #[test]
+    fn malformed_input_reports_the_line() {
+        let cases = [
+            "v 0 0\n",
+            "v 0 0 zero\n",
+            "v 0 0 0\nv 1 0 0\nf 1 2\n",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2/1 3/1\n",
+        ];
+        for src in cases {
+            match parse(src) {
+                Err(ObjError::Parse { line, .. }) => assert_eq!(line, src.lines().count()),
+                other => panic!("{src:?} gave {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn mtl_errors_name_the_library() {
+        let result = Model::from_obj_with_mtl("mtllib bad.mtl\n".as_bytes(), |_| {
+            Ok(Some("Kd 1 1 1\n".as_bytes()))
+        });
+        match result {
+            Err(ObjError::Mtl { file, source }) => {
+                assert_eq!(file, "bad.mtl");
+                assert!(matches!(*source, ObjError::Parse { line: 1, .. }));
+            }
+            other => panic!("expected an mtl error, got {other:?}"),
+        }
+    }
+}
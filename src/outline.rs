@@ -0,0 +1,387 @@
+// Selection highlight. The selected instance gets rendered into a seed
+// texture, a jump flood spreads the nearest covered pixel across the screen
+// and a last pass blends the outline over the HDR target wherever that pixel
+// is close enough. There's no depth test, so a selection stays visible
+// behind whatever is in front of it
+use bytemuck::{Pod, Zeroable};
+use glam::Vec4;
+use wgpu::util::DeviceExt;
+
+use crate::camera::CameraUniform;
+use crate::instance::InstanceRaw;
+use crate::preprocessor::Preprocessor;
+use crate::reflect::{ReflectError, ShaderReflection};
+use crate::scene::{ObjectUniform, Scene, Selection};
+use crate::texture::Texture;
+use crate::vert::Vert;
+use crate::viewport::{Viewport, ViewportDependent};
+
+const OUTLINE_SHADER: &str = "outline.wgsl";
+const SEED_SHADER: &str = "outline_seed.wgsl";
+// Offset to the nearest seed and whether there is one. Rg32Float can't be
+// rendered to on WebGL, relative offsets stay small enough for f16 to be exact
+const SEED_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// In pixels. Every doubling costs another jump flood pass
+pub const MAX_WIDTH: u32 = 64;
+// One uniform slot per jump flood pass plus the composite one
+const SLOTS: u64 = MAX_WIDTH.ilog2() as u64 + 2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct OutlineConfig {
+    // Linear HDR color, alpha blends it over the scene
+    pub color: Vec4,
+    // In pixels, clamped to 1..=MAX_WIDTH
+    pub width: f32,
+    // Mixed over the object itself, alpha is the strength
+    pub tint: Option<Vec4>,
+}
+
+impl Default for OutlineConfig {
+    fn default() -> Self {
+        Self {
+            color: Vec4::new(1.0, 0.45, 0.05, 1.0),
+            width: 3.0,
+            tint: None,
+        }
+    }
+}
+
+impl OutlineConfig {
+    // Jump distances largest first, halving down to a single pixel
+    fn steps(&self) -> impl Iterator<Item = u32> {
+        let first = (self.width.clamp(1.0, MAX_WIDTH as f32).ceil() as u32).next_power_of_two();
+        std::iter::successors(Some(first), |step| (*step > 1).then_some(step / 2))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    tint: [f32; 4],
+    // width, jump distance
+    params: [f32; 4],
+}
+
+impl OutlineUniform {
+    fn new(config: &OutlineConfig, step: u32) -> Self {
+        Self {
+            color: config.color.to_array(),
+            tint: config.tint.unwrap_or(Vec4::ZERO).to_array(),
+            params: [
+                config.width.clamp(1.0, MAX_WIDTH as f32),
+                step as f32,
+                0.0,
+                0.0,
+            ],
+        }
+    }
+}
+
+pub struct Outline {
+    pub config: OutlineConfig,
+    // SLOTS uniforms, picked per pass with a dynamic offset
+    buffer: wgpu::Buffer,
+    slot_size: u64,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // Ping-ponged between passes, bind_groups[i] reads seeds[i]
+    seeds: [Texture; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+    seed_pipeline: wgpu::RenderPipeline,
+    jump_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Outline {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        object_layout: &wgpu::BindGroupLayout,
+        viewport: &Viewport,
+        config: OutlineConfig,
+    ) -> Result<Self, ReflectError> {
+        ShaderReflection::embedded(SEED_SHADER).and_then(|reflection| {
+            reflection.validate(
+                &[CameraUniform::new().bind_desc(), ObjectUniform::bind_desc()],
+                &[Vert::desc(), InstanceRaw::desc()],
+            )
+        })?;
+        ShaderReflection::embedded(OUTLINE_SHADER)
+            .and_then(|reflection| reflection.validate(&[Self::bind_desc()], &[]))?;
+        let seed_shader = Preprocessor::standard().load_embedded(device, SEED_SHADER);
+        let shader = Preprocessor::standard().load_embedded(device, OUTLINE_SHADER);
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let slot_size = (size_of::<OutlineUniform>() as u64).next_multiple_of(alignment);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline buffer"),
+            contents: &Self::uniform_slots(&config, slot_size),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&Self::bind_desc());
+        let seeds = Self::create_seeds(device, viewport);
+        let bind_groups = Self::create_bind_groups(device, &bind_group_layout, &seeds, &buffer);
+
+        let seed_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Seed Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, object_layout],
+            push_constant_ranges: &[],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vertex_buffers = [Vert::desc(), InstanceRaw::desc()];
+        let pipeline = |label, layout, shader, buffers, entry_point, format, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                // No culling, the back faces are part of the silhouette too
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let seed_pipeline = pipeline(
+            "Outline Seed Pipeline",
+            &seed_layout,
+            &seed_shader,
+            &vertex_buffers,
+            "fs_main",
+            SEED_FORMAT,
+            None,
+        );
+        let jump_pipeline = pipeline(
+            "Outline Jump Pipeline",
+            &layout,
+            &shader,
+            &[],
+            "fs_jump",
+            SEED_FORMAT,
+            None,
+        );
+        let composite_pipeline = pipeline(
+            "Outline Composite Pipeline",
+            &layout,
+            &shader,
+            &[],
+            "fs_composite",
+            viewport.hdr_format,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
+
+        Ok(Self {
+            config,
+            buffer,
+            slot_size,
+            bind_group_layout,
+            seeds,
+            bind_groups,
+            seed_pipeline,
+            jump_pipeline,
+            composite_pipeline,
+        })
+    }
+
+    pub fn set_config(&mut self, queue: &wgpu::Queue, config: OutlineConfig) {
+        self.config = config;
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            &Self::uniform_slots(&self.config, self.slot_size),
+        );
+    }
+
+    // Outlines `object` on top of `target`, which has to be
+    // the single sampled HDR target the scene just got resolved into
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        scene: &Scene,
+        selection: Selection,
+    ) {
+        {
+            let mut seed_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Seed Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.seeds[0].view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // Alpha 0 means no seed
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            seed_pass.set_pipeline(&self.seed_pipeline);
+            seed_pass.set_bind_group(0, camera_bind_group, &[]);
+            scene.draw_instance_depth(&mut seed_pass, selection, 1);
+        }
+
+        // Each pass reads the seeds the previous one wrote
+        let mut current = 0;
+        for pass in 0..self.config.steps().count() {
+            let mut jump_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Jump Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.seeds[1 - current].view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            jump_pass.set_pipeline(&self.jump_pipeline);
+            jump_pass.set_bind_group(0, &self.bind_groups[current], &[self.slot_offset(pass + 1)]);
+            jump_pass.draw(0..3, 0..1);
+            current = 1 - current;
+        }
+
+        let mut composite_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        composite_pass.set_pipeline(&self.composite_pipeline);
+        composite_pass.set_bind_group(0, &self.bind_groups[current], &[self.slot_offset(0)]);
+        composite_pass.draw(0..3, 0..1);
+    }
+
+    fn slot_offset(&self, slot: usize) -> u32 {
+        (slot as u64 * self.slot_size) as u32
+    }
+
+    // Slot 0 is for the composite, the jump flood passes follow in order
+    fn uniform_slots(config: &OutlineConfig, slot_size: u64) -> Vec<u8> {
+        let mut bytes = vec![0; (slot_size * SLOTS) as usize];
+        let uniforms = std::iter::once(0).chain(config.steps());
+        for (slot, step) in uniforms.enumerate() {
+            let start = slot * slot_size as usize;
+            let uniform = OutlineUniform::new(config, step);
+            bytes[start..start + size_of::<OutlineUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        bytes
+    }
+
+    fn create_seeds(device: &wgpu::Device, viewport: &Viewport) -> [Texture; 2] {
+        ["outline_seed_texture_a", "outline_seed_texture_b"].map(|label| {
+            Texture::create_render_target(
+                device,
+                viewport.size.width,
+                viewport.size.height,
+                SEED_FORMAT,
+                1,
+                Some(label),
+            )
+        })
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        seeds: &[Texture; 2],
+        buffer: &wgpu::Buffer,
+    ) -> [wgpu::BindGroup; 2] {
+        seeds.each_ref().map(|seed| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&seed.view),
+                    },
+                    // One slot at a time, the dynamic offset picks which
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(size_of::<OutlineUniform>() as u64),
+                        }),
+                    },
+                ],
+                label: Some("outline_bind_group"),
+            })
+        })
+    }
+
+    fn bind_desc<'a>() -> wgpu::BindGroupLayoutDescriptor<'a> {
+        wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("outline_bind_group_layout"),
+        }
+    }
+}
+
+// Single sampled and depth free, only the size matters
+impl ViewportDependent for Outline {
+    fn viewport_changed(&mut self, device: &wgpu::Device, viewport: &Viewport) {
+        self.seeds = Self::create_seeds(device, viewport);
+        self.bind_groups =
+            Self::create_bind_groups(device, &self.bind_group_layout, &self.seeds, &self.buffer);
+    }
+}
@@ -0,0 +1,200 @@
+// CPU side ray casting against scene geometry, for clicking on things
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::model::Model;
+use crate::scene::ObjectId;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // Direction isn't renormalized so distances along the
+    // transformed ray match the ones along this one
+    pub fn transform(&self, matrix: Mat4) -> Ray {
+        Ray {
+            origin: matrix.transform_point3(self.origin),
+            direction: matrix.transform_vector3(self.direction),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(
+            Aabb {
+                min: Vec3::INFINITY,
+                max: Vec3::NEG_INFINITY,
+            },
+            |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        )
+    }
+
+    // Slab test, returns the distance to where the ray enters
+    // the box (0 when it starts inside)
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let inverse = ray.direction.recip();
+        let t1 = (self.min - ray.origin) * inverse;
+        let t2 = (self.max - ray.origin) * inverse;
+
+        let near = t1.min(t2).max_element().max(0.0);
+        let far = t1.max(t2).min_element();
+        (near <= far).then_some(near)
+    }
+}
+
+// Möller-Trumbore, both faces count. Returns the distance along the ray
+pub fn intersect_triangle(ray: &Ray, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < f32::EPSILON {
+        // Parallel to the triangle
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_det;
+    (t > 0.0).then_some(t)
+}
+
+// Copy of a model's triangles kept around for picking, in object space
+pub struct PickGeometry {
+    pub positions: Vec<Vec3>,
+    pub indices: Vec<u32>,
+    pub bounds: Aabb,
+}
+
+impl PickGeometry {
+    pub fn new(model: &Model) -> Self {
+        let positions: Vec<Vec3> = model
+            .verts
+            .iter()
+            .map(|vert| Vec4::from_array(vert.pos).truncate())
+            .collect();
+
+        Self {
+            bounds: Aabb::from_points(positions.iter().copied()),
+            positions,
+            indices: model.indicies.clone(),
+        }
+    }
+
+    pub fn triangle(&self, index: usize) -> [Vec3; 3] {
+        let tri = &self.indices[index * 3..index * 3 + 3];
+        [0, 1, 2].map(|corner| self.positions[tri[corner] as usize])
+    }
+
+    // Closest triangle along the ray, as the distance and triangle index
+    pub fn raycast(&self, ray: &Ray) -> Option<(f32, usize)> {
+        self.bounds.intersect(ray)?;
+
+        (0..self.indices.len() / 3)
+            .filter_map(|index| {
+                intersect_triangle(ray, self.triangle(index)).map(|distance| (distance, index))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+// How clicking finds the object under the cursor
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PickMode {
+    // Ray against the triangles on the CPU, gives the exact hit
+    #[default]
+    Ray,
+    // Reads the ID buffer back from the GPU, object only but
+    // doesn't care how dense the meshes are
+    IdBuffer,
+}
+
+// What a pick ray hit
+#[derive(Copy, Clone, Debug)]
+pub struct Hit {
+    pub object: ObjectId,
+    // Which of the object's instances
+    pub instance: usize,
+    pub triangle: usize,
+    // World space, the normal faces back towards the ray
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    #[test]
+    fn aabb_intersect() {
+        let aabb = Aabb::from_points([Vec3::NEG_ONE, Vec3::ONE]);
+
+        let hit = aabb.intersect(&Ray::new(Vec3::new(-5.0, 0.5, 0.5), Vec3::X));
+        assert_eq!(hit, Some(4.0));
+        // Starting inside counts as hitting right away
+        let inside = aabb.intersect(&Ray::new(Vec3::new(0.5, 0.5, 0.5), Vec3::X));
+        assert_eq!(inside, Some(0.0));
+
+        assert_eq!(
+            aabb.intersect(&Ray::new(Vec3::new(-5.0, 2.0, 0.5), Vec3::X)),
+            None
+        );
+        // Box behind the ray
+        assert_eq!(
+            aabb.intersect(&Ray::new(Vec3::new(5.0, 0.5, 0.5), Vec3::X)),
+            None
+        );
+    }
+
+    #[test]
+    fn triangle_hits_from_both_sides() {
+        let front = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::NEG_Z);
+        let back = Ray::new(Vec3::new(0.25, 0.25, -2.0), Vec3::Z);
+        assert_eq!(intersect_triangle(&front, TRIANGLE), Some(1.0));
+        assert_eq!(intersect_triangle(&back, TRIANGLE), Some(2.0));
+    }
+
+    #[test]
+    fn triangle_misses() {
+        let outside = Ray::new(Vec3::new(0.75, 0.75, 1.0), Vec3::NEG_Z);
+        let behind = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::Z);
+        let parallel = Ray::new(Vec3::new(-1.0, 0.25, 0.0), Vec3::X);
+        assert_eq!(intersect_triangle(&outside, TRIANGLE), None);
+        assert_eq!(intersect_triangle(&behind, TRIANGLE), None);
+        assert_eq!(intersect_triangle(&parallel, TRIANGLE), None);
+    }
+}
@@ -0,0 +1,537 @@
+// Small WGSL preprocessor so shaders can share code. Understands
+//
+//   #include "common.wgsl"   pasted in once, later includes of the same file are skipped
+//                            (a file including itself, even indirectly, is an error)
+//   #define NAME [value]     flag for #ifdef, a value also replaces NAME in the code below
+//   #undef NAME
+//   #ifdef NAME / #ifndef NAME / #else / #endif
+//
+// Constants added with `with_constant` are emitted as WGSL consts at the top
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+};
+
+use crate::light::MAX_LIGHTS;
+
+// Every shader under src/shaders, includes get resolved against these
+// unless the caller brings its own loader (hot reloading does)
+const EMBEDDED: &[(&str, &str)] = &[
+    ("common.wgsl", include_str!("shaders/common.wgsl")),
+    ("id.wgsl", include_str!("shaders/id.wgsl")),
+    ("lights.wgsl", include_str!("shaders/lights.wgsl")),
+    ("lit.wgsl", include_str!("shaders/lit.wgsl")),
+    ("outline.wgsl", include_str!("shaders/outline.wgsl")),
+    (
+        "outline_seed.wgsl",
+        include_str!("shaders/outline_seed.wgsl"),
+    ),
+    ("pbr.wgsl", include_str!("shaders/pbr.wgsl")),
+    ("shadow.wgsl", include_str!("shaders/shadow.wgsl")),
+    ("shadowing.wgsl", include_str!("shaders/shadowing.wgsl")),
+    ("skybox.wgsl", include_str!("shaders/skybox.wgsl")),
+    ("tonemap.wgsl", include_str!("shaders/tonemap.wgsl")),
+    ("wireframe.wgsl", include_str!("shaders/wireframe.wgsl")),
+];
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    // Not embedded and the loader didn't find it either
+    Missing(String),
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Io {
+        file: String,
+        source: io::Error,
+    },
+    RecursiveInclude(String),
+    Directive {
+        file: String,
+        line: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::Missing(file) => write!(f, "shader {file} not found"),
+            PreprocessError::Io { file, source } => write!(f, "{file}: {source}"),
+            PreprocessError::RecursiveInclude(file) => write!(f, "{file} includes itself"),
+            PreprocessError::Directive { file, line, reason } => {
+                write!(f, "{file}:{line}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PreprocessError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub fn embedded(name: &str) -> Option<&'static str> {
+    EMBEDDED
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|(_, source)| *source)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Preprocessor {
+    defines: HashMap<String, String>,
+    // Name, type and value
+    constants: Vec<(String, String, String)>,
+}
+
+// Where we are in an #ifdef block
+struct Conditional {
+    // Lines get emitted
+    active: bool,
+    // Whether the enclosing block is active, #else can't turn on more than that
+    parent_active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+// Per run state, the preprocessor itself stays reusable
+struct Context<'a, L> {
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    stack: Vec<String>,
+    load: &'a mut L,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // What every shader in this project gets, constants that
+    // have to agree with the Rust side live here
+    pub fn standard() -> Self {
+        let preprocessor =
+            Self::new().with_constant("MAX_LIGHTS", "u32", &format!("{MAX_LIGHTS}u"));
+
+        // No storage buffers or polygon modes on WebGL2
+        if cfg!(target_arch = "wasm32") {
+            preprocessor.with_define("WEBGL", "")
+        } else {
+            preprocessor
+        }
+    }
+
+    pub fn with_define(mut self, name: &str, value: &str) -> Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_constant(mut self, name: &str, ty: &str, value: &str) -> Self {
+        self.constants
+            .push((name.to_string(), ty.to_string(), value.to_string()));
+        self
+    }
+
+    // Runs an embedded shader through the preprocessor
+    pub fn process_embedded(&self, name: &str) -> Result<String, PreprocessError> {
+        self.process(name, |file| {
+            embedded(file)
+                .map(str::to_string)
+                .ok_or_else(|| PreprocessError::Missing(file.to_string()))
+        })
+    }
+
+    // `load` gets called for `name` and every file it includes
+    pub fn process<L>(&self, name: &str, mut load: L) -> Result<String, PreprocessError>
+    where
+        L: FnMut(&str) -> Result<String, PreprocessError>,
+    {
+        let mut output = String::new();
+        for (name, ty, value) in &self.constants {
+            output.push_str(&format!("const {name}: {ty} = {value};\n"));
+        }
+
+        let mut context = Context {
+            defines: self.defines.clone(),
+            included: HashSet::new(),
+            stack: Vec::new(),
+            load: &mut load,
+        };
+        Self::process_file(name, &mut context, &mut output)?;
+        Ok(output)
+    }
+
+    // Embedded shaders are part of the build, if they don't preprocess
+    // that is a bug rather than something to recover from
+    pub fn load_embedded(&self, device: &wgpu::Device, name: &str) -> wgpu::ShaderModule {
+        let source = self
+            .process_embedded(name)
+            .unwrap_or_else(|err| panic!("Failed to preprocess {name}: {err}"));
+
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+    }
+
+    fn process_file<L>(
+        name: &str,
+        context: &mut Context<L>,
+        output: &mut String,
+    ) -> Result<(), PreprocessError>
+    where
+        L: FnMut(&str) -> Result<String, PreprocessError>,
+    {
+        if context.stack.iter().any(|file| file == name) {
+            return Err(PreprocessError::RecursiveInclude(name.to_string()));
+        }
+        context.included.insert(name.to_string());
+        context.stack.push(name.to_string());
+
+        let source = (context.load)(name)?;
+        let mut conditionals: Vec<Conditional> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |reason: String| PreprocessError::Directive {
+                file: name.to_string(),
+                line: line_number,
+                reason,
+            };
+            let active = conditionals.last().is_none_or(|c| c.active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    output.push_str(&substitute(line, &context.defines));
+                    output.push('\n');
+                }
+                continue;
+            };
+
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map(|(keyword, argument)| (keyword, argument.trim()))
+                .unwrap_or((directive.trim(), ""));
+
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    if argument.is_empty() {
+                        return Err(error(format!("#{keyword} needs a name")));
+                    }
+                    let defined = context.defines.contains_key(argument);
+                    conditionals.push(Conditional {
+                        active: active && (defined == (keyword == "ifdef")),
+                        parent_active: active,
+                        seen_else: false,
+                        line: line_number,
+                    });
+                }
+                "else" => {
+                    let Some(conditional) = conditionals.last_mut() else {
+                        return Err(error("#else without #ifdef".to_string()));
+                    };
+                    if conditional.seen_else {
+                        return Err(error("second #else in the same block".to_string()));
+                    }
+                    conditional.seen_else = true;
+                    conditional.active = conditional.parent_active && !conditional.active;
+                }
+                "endif" => {
+                    if conditionals.pop().is_none() {
+                        return Err(error("#endif without #ifdef".to_string()));
+                    }
+                }
+                // Everything below only counts in active blocks
+                _ if !active => {}
+                "include" => {
+                    let Some(file) = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                    else {
+                        return Err(error(format!("expected #include \"file\", got {argument}")));
+                    };
+                    // Still being processed means a cycle, process_file reports
+                    // that. Only files that are done get skipped
+                    let cycle = context.stack.iter().any(|open| open == file);
+                    if cycle || !context.included.contains(file) {
+                        Self::process_file(file, context, output)?;
+                    }
+                }
+                "define" => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .map(|(define, value)| (define, value.trim()))
+                        .unwrap_or((argument, ""));
+                    if define.is_empty() {
+                        return Err(error("#define needs a name".to_string()));
+                    }
+                    context
+                        .defines
+                        .insert(define.to_string(), value.to_string());
+                }
+                "undef" => {
+                    context.defines.remove(argument);
+                }
+                _ => return Err(error(format!("unknown directive #{keyword}"))),
+            }
+        }
+
+        if let Some(conditional) = conditionals.last() {
+            return Err(PreprocessError::Directive {
+                file: name.to_string(),
+                line: conditional.line,
+                reason: "#ifdef is never closed".to_string(),
+            });
+        }
+
+        context.stack.pop();
+        Ok(())
+    }
+}
+
+// Replaces defines that have a value, whole identifiers only. Numbers
+// and // comments are copied as they are, so a define like `e` can't
+// eat the exponent of 1e-3
+fn substitute(line: &str, defines: &HashMap<String, String>) -> String {
+    if defines.values().all(String::is_empty) {
+        return line.to_string();
+    }
+
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_alphanumeric() || c == '_' || c == '/') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("//") {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('/') {
+            output.push('/');
+            rest = after;
+            continue;
+        }
+
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = numeric_literal_len(rest);
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let identifier = &rest[..end];
+
+        match defines.get(identifier) {
+            Some(value) if !value.is_empty() => output.push_str(value),
+            _ => output.push_str(identifier),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// Length of the number at the start of `text`, including suffixes
+// like 1u or 2.0f and exponents like 1e-3 or 0x1p+4
+fn numeric_literal_len(text: &str) -> usize {
+    let mut previous = ' ';
+    text.find(|c: char| {
+        let exponent_sign = (c == '+' || c == '-') && matches!(previous, 'e' | 'E' | 'p' | 'P');
+        previous = c;
+        !(c.is_alphanumeric() || c == '_' || c == '.' || exponent_sign)
+    })
+    .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs `main` with the other files given as (name, source) pairs
+    fn run(preprocessor: &Preprocessor, files: &[(&str, &str)]) -> Result<String, PreprocessError> {
+        preprocessor.process(files[0].0, |file| {
+            files
+                .iter()
+                .find(|(name, _)| *name == file)
+                .map(|(_, source)| source.to_string())
+                .ok_or_else(|| PreprocessError::Missing(file.to_string()))
+        })
+    }
+
+    fn lines(output: &str) -> Vec<&str> {
+        output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn includes_are_inlined_once() {
+        let files = [
+            (
+                "main.wgsl",
+                "#include \"a.wgsl\"\n#include \"b.wgsl\"\nfn main() {}",
+            ),
+            ("a.wgsl", "#include \"b.wgsl\"\nfn a() {}"),
+            ("b.wgsl", "fn b() {}"),
+        ];
+        let output = run(&Preprocessor::new(), &files).unwrap();
+        assert_eq!(lines(&output), ["fn b() {}", "fn a() {}", "fn main() {}"]);
+    }
+
+    #[test]
+    fn recursive_includes_are_errors() {
+        let files = [
+            ("main.wgsl", "#include \"self.wgsl\""),
+            ("self.wgsl", "#include \"self.wgsl\"\nfn s() {}"),
+        ];
+        assert!(matches!(
+            run(&Preprocessor::new(), &files),
+            Err(PreprocessError::RecursiveInclude(file)) if file == "self.wgsl"
+        ));
+
+        let files = [
+            ("main.wgsl", "#include \"a.wgsl\""),
+            ("a.wgsl", "#include \"b.wgsl\""),
+            ("b.wgsl", "#include \"a.wgsl\""),
+        ];
+        assert!(matches!(
+            run(&Preprocessor::new(), &files),
+            Err(PreprocessError::RecursiveInclude(file)) if file == "a.wgsl"
+        ));
+    }
+
+    #[test]
+    fn include_errors() {
+        let files = [("main.wgsl", "#include \"gone.wgsl\"")];
+        assert!(matches!(
+            run(&Preprocessor::new(), &files),
+            Err(PreprocessError::Missing(file)) if file == "gone.wgsl"
+        ));
+
+        let files = [("main.wgsl", "\n#include gone.wgsl")];
+        assert!(matches!(
+            run(&Preprocessor::new(), &files),
+            Err(PreprocessError::Directive { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers() {
+        let files = [(
+            "main.wgsl",
+            "#define SIZE 4u\nlet a = SIZE + SIZED + SIZE_2;\n#undef SIZE\nlet b = SIZE;",
+        )];
+        let output = run(&Preprocessor::new(), &files).unwrap();
+        assert_eq!(
+            lines(&output),
+            ["let a = 4u + SIZED + SIZE_2;", "let b = SIZE;"]
+        );
+    }
+
+    #[test]
+    fn defines_skip_numbers_and_comments() {
+        let preprocessor = Preprocessor::new()
+            .with_define("e", "E_VALUE")
+            .with_define("u", "U_VALUE");
+        let files = [(
+            "main.wgsl",
+            "let x = 1e-3 + 2.5e+2 * e + 0x1p-4 + 3u; // e stays here\nlet y = u / 2.0;",
+        )];
+        let output = run(&preprocessor, &files).unwrap();
+        assert_eq!(
+            lines(&output),
+            [
+                "let x = 1e-3 + 2.5e+2 * E_VALUE + 0x1p-4 + 3u; // e stays here",
+                "let y = U_VALUE / 2.0;"
+            ]
+        );
+    }
+
+    #[test]
+    fn ifdef_else_nesting() {
+        let source = "\
+#ifdef A
+a
+#ifndef B
+a_not_b
+#else
+a_and_b
+#endif
+#else
+not_a
+#ifdef B
+never
+#endif
+#endif";
+        let files = [("main.wgsl", source)];
+
+        let output = run(&Preprocessor::new(), &files).unwrap();
+        assert_eq!(lines(&output), ["not_a"]);
+
+        let output = run(&Preprocessor::new().with_define("A", ""), &files).unwrap();
+        assert_eq!(lines(&output), ["a", "a_not_b"]);
+
+        let both = Preprocessor::new()
+            .with_define("A", "")
+            .with_define("B", "");
+        let output = run(&both, &files).unwrap();
+        assert_eq!(lines(&output), ["a", "a_and_b"]);
+    }
+
+    #[test]
+    fn defines_in_inactive_blocks_are_ignored() {
+        let files = [(
+            "main.wgsl",
+            "#ifdef MISSING\n#define X 1\n#endif\n#ifdef X\nbad\n#endif\nok",
+        )];
+        let output = run(&Preprocessor::new(), &files).unwrap();
+        assert_eq!(lines(&output), ["ok"]);
+    }
+
+    #[test]
+    fn unbalanced_conditionals_are_errors() {
+        for (source, line) in [
+            ("\n#ifdef A\nx", 2),
+            ("#endif", 1),
+            ("#else", 1),
+            ("#ifdef A\n#else\n#else\n#endif", 3),
+            ("#ifdef\n#endif", 1),
+            ("#pragma once", 1),
+        ] {
+            match run(&Preprocessor::new(), &[("main.wgsl", source)]) {
+                Err(PreprocessError::Directive { line: got, .. }) => {
+                    assert_eq!(got, line, "{source:?}")
+                }
+                other => panic!("{source:?} gave {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn constants_come_first() {
+        let preprocessor = Preprocessor::new().with_constant("COUNT", "u32", "3u");
+        let output = run(&preprocessor, &[("main.wgsl", "fn main() {}")]).unwrap();
+        assert_eq!(lines(&output), ["const COUNT: u32 = 3u;", "fn main() {}"]);
+    }
+
+    #[test]
+    fn embedded_shaders_preprocess() {
+        for (name, _) in EMBEDDED {
+            for preprocessor in [
+                Preprocessor::standard(),
+                Preprocessor::standard().with_define("WEBGL", ""),
+            ] {
+                if let Err(err) = preprocessor.process_embedded(name) {
+                    panic!("{name}: {err}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,448 @@
+// Reads bind groups and vertex inputs straight out of WGSL with naga, so the
+// layouts written on the Rust side can be checked against what the shaders
+// actually declare instead of failing deep inside pipeline creation
+use std::fmt;
+
+use wgpu::naga;
+
+use crate::preprocessor::{PreprocessError, Preprocessor};
+
+#[derive(Debug)]
+pub enum ReflectError {
+    // The embedded shader didn't make it through the preprocessor
+    Preprocess {
+        shader: String,
+        source: PreprocessError,
+    },
+    // Parse or validation error, already formatted with the source
+    Invalid {
+        shader: String,
+        message: String,
+    },
+    // Everything that didn't line up, one readable line each
+    Mismatch {
+        shader: String,
+        problems: Vec<String>,
+    },
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectError::Preprocess { shader, source } => {
+                write!(f, "failed to preprocess {shader}: {source}")
+            }
+            ReflectError::Invalid { shader, message } => write!(f, "{shader}: {message}"),
+            ReflectError::Mismatch { shader, problems } => {
+                write!(f, "{shader} doesn't match its pipeline:")?;
+                for problem in problems {
+                    write!(f, "\n  {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReflectError::Preprocess { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// A resource the shader declares with @group/@binding
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    pub name: String,
+    pub group: u32,
+    // Visibility covers the stages of the entry points that use it
+    pub entry: wgpu::BindGroupLayoutEntry,
+}
+
+// A @location input of a vertex entry point
+#[derive(Clone, Debug)]
+pub struct ReflectedInput {
+    pub name: String,
+    pub location: u32,
+    pub kind: naga::ScalarKind,
+    pub components: u32,
+}
+
+pub struct ShaderReflection {
+    name: String,
+    module: naga::Module,
+    info: naga::valid::ModuleInfo,
+}
+
+impl ShaderReflection {
+    pub fn new(name: &str, source: &str) -> Result<Self, ReflectError> {
+        let invalid = |message| ReflectError::Invalid {
+            shader: name.to_string(),
+            message,
+        };
+
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|err| invalid(err.emit_to_string_with_path(source, name)))?;
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|err| invalid(err.emit_to_string_with_path(source, name)))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            module,
+            info,
+        })
+    }
+
+    // Reflects one of the embedded shaders after preprocessing
+    pub fn embedded(name: &str) -> Result<Self, ReflectError> {
+        let source = Preprocessor::standard()
+            .process_embedded(name)
+            .map_err(|source| ReflectError::Preprocess {
+                shader: name.to_string(),
+                source,
+            })?;
+        Self::new(name, &source)
+    }
+
+    // Every resource the entry points use, sorted by group and binding.
+    // Unused declarations are left out, wgpu doesn't need them either
+    pub fn bindings(&self) -> Vec<ReflectedBinding> {
+        let mut bindings: Vec<_> = self
+            .module
+            .global_variables
+            .iter()
+            .filter_map(|(handle, global)| {
+                let binding = global.binding.as_ref()?;
+                let ty = binding_type(&self.module, global)?;
+
+                let mut visibility = wgpu::ShaderStages::NONE;
+                for (index, entry_point) in self.module.entry_points.iter().enumerate() {
+                    if !self.info.get_entry_point(index)[handle].is_empty() {
+                        visibility |= shader_stage(entry_point.stage);
+                    }
+                }
+                if visibility.is_empty() {
+                    return None;
+                }
+
+                Some(ReflectedBinding {
+                    name: global.name.clone().unwrap_or_default(),
+                    group: binding.group,
+                    entry: wgpu::BindGroupLayoutEntry {
+                        binding: binding.binding,
+                        visibility,
+                        ty,
+                        count: None,
+                    },
+                })
+            })
+            .collect();
+        bindings.sort_by_key(|binding| (binding.group, binding.entry.binding));
+        bindings
+    }
+
+    // Location inputs of a vertex entry point, struct members included
+    pub fn vertex_inputs(&self, entry_point: &str) -> Option<Vec<ReflectedInput>> {
+        let entry_point = self
+            .module
+            .entry_points
+            .iter()
+            .find(|ep| ep.name == entry_point && ep.stage == naga::ShaderStage::Vertex)?;
+
+        let mut inputs = Vec::new();
+        for argument in &entry_point.function.arguments {
+            let name = argument.name.clone().unwrap_or_default();
+            match &self.module.types[argument.ty].inner {
+                naga::TypeInner::Struct { members, .. } => {
+                    for member in members {
+                        let member_name = member.name.clone().unwrap_or_default();
+                        inputs.extend(self.input(
+                            format!("{name}.{member_name}"),
+                            member.binding.as_ref(),
+                            member.ty,
+                        ));
+                    }
+                }
+                _ => inputs.extend(self.input(name, argument.binding.as_ref(), argument.ty)),
+            }
+        }
+        Some(inputs)
+    }
+
+    // Checks the hand written layouts (indexed by group) and vertex buffers
+    // against the shader, collecting every mismatch instead of stopping early
+    pub fn validate(
+        &self,
+        groups: &[wgpu::BindGroupLayoutDescriptor],
+        buffers: &[wgpu::VertexBufferLayout],
+    ) -> Result<(), ReflectError> {
+        let mut problems = Vec::new();
+
+        for binding in self.bindings() {
+            let (group, number) = (binding.group, binding.entry.binding);
+            let Some(layout) = groups.get(group as usize) else {
+                problems.push(format!(
+                    "`{}` uses group {group}, the pipeline only has {} groups",
+                    binding.name,
+                    groups.len()
+                ));
+                continue;
+            };
+            let Some(entry) = layout.entries.iter().find(|e| e.binding == number) else {
+                problems.push(format!(
+                    "`{}` (group {group}, binding {number}) is missing from {}",
+                    binding.name,
+                    layout.label.unwrap_or("the layout")
+                ));
+                continue;
+            };
+
+            if !types_compatible(&entry.ty, &binding.entry.ty) {
+                problems.push(format!(
+                    "`{}` (group {group}, binding {number}) is {} in the shader but {} in {}",
+                    binding.name,
+                    describe_type(&binding.entry.ty),
+                    describe_type(&entry.ty),
+                    layout.label.unwrap_or("the layout")
+                ));
+            }
+            if !entry.visibility.contains(binding.entry.visibility) {
+                problems.push(format!(
+                    "`{}` (group {group}, binding {number}) is used in {:?} but only visible to {:?}",
+                    binding.name, binding.entry.visibility, entry.visibility
+                ));
+            }
+        }
+
+        let attributes: Vec<_> = buffers
+            .iter()
+            .flat_map(|buffer| buffer.attributes.iter())
+            .collect();
+        let vertex_entry_points = self
+            .module
+            .entry_points
+            .iter()
+            .filter(|ep| ep.stage == naga::ShaderStage::Vertex);
+        for entry_point in vertex_entry_points {
+            for input in self.vertex_inputs(&entry_point.name).unwrap_or_default() {
+                let Some(attribute) = attributes
+                    .iter()
+                    .find(|attribute| attribute.shader_location == input.location)
+                else {
+                    problems.push(format!(
+                        "{}: `{}` reads @location({}) which no vertex buffer provides",
+                        entry_point.name, input.name, input.location
+                    ));
+                    continue;
+                };
+
+                // A different component count is fine, missing ones get
+                // filled in, but the scalar type has to match
+                if let Some(kind) = vertex_format_kind(attribute.format)
+                    && kind != input.kind
+                {
+                    problems.push(format!(
+                        "{}: `{}` at @location({}) is {} in the shader but the buffer gives {:?}",
+                        entry_point.name,
+                        input.name,
+                        input.location,
+                        describe_input(input.kind, input.components),
+                        attribute.format
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ReflectError::Mismatch {
+                shader: self.name.clone(),
+                problems,
+            })
+        }
+    }
+
+    fn input(
+        &self,
+        name: String,
+        binding: Option<&naga::Binding>,
+        ty: naga::Handle<naga::Type>,
+    ) -> Option<ReflectedInput> {
+        let Some(naga::Binding::Location { location, .. }) = binding else {
+            return None;
+        };
+        let (scalar, components) = match self.module.types[ty].inner {
+            naga::TypeInner::Scalar(scalar) => (scalar, 1),
+            naga::TypeInner::Vector { size, scalar } => (scalar, size as u32),
+            _ => return None,
+        };
+
+        Some(ReflectedInput {
+            name,
+            location: *location,
+            kind: scalar.kind,
+            components,
+        })
+    }
+}
+
+fn shader_stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+fn binding_type(module: &naga::Module, global: &naga::GlobalVariable) -> Option<wgpu::BindingType> {
+    match global.space {
+        naga::AddressSpace::Uniform => Some(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }),
+        naga::AddressSpace::Storage { access } => Some(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }),
+        naga::AddressSpace::Handle => match module.types[global.ty].inner {
+            naga::TypeInner::Sampler { comparison } => {
+                Some(wgpu::BindingType::Sampler(if comparison {
+                    wgpu::SamplerBindingType::Comparison
+                } else {
+                    wgpu::SamplerBindingType::Filtering
+                }))
+            }
+            naga::TypeInner::Image {
+                dim,
+                arrayed,
+                class,
+            } => {
+                let view_dimension = match (dim, arrayed) {
+                    (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                    (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                    (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                    (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                    (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                    (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                };
+                let (sample_type, multisampled) = match class {
+                    naga::ImageClass::Sampled { kind, multi } => (
+                        match kind {
+                            naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            _ => wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        multi,
+                    ),
+                    naga::ImageClass::Depth { multi } => (wgpu::TextureSampleType::Depth, multi),
+                    // Storage textures would need a format mapping, nothing uses them yet
+                    naga::ImageClass::Storage { .. } => return None,
+                };
+                Some(wgpu::BindingType::Texture {
+                    sample_type,
+                    view_dimension,
+                    multisampled,
+                })
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Only what the shader can actually see, filtering and
+// dynamic offsets are up to the Rust side
+fn types_compatible(layout: &wgpu::BindingType, shader: &wgpu::BindingType) -> bool {
+    use wgpu::BindingType::*;
+    match (layout, shader) {
+        (Buffer { ty: a, .. }, Buffer { ty: b, .. }) => a == b,
+        (
+            Sampler(wgpu::SamplerBindingType::Comparison),
+            Sampler(wgpu::SamplerBindingType::Comparison),
+        ) => true,
+        (Sampler(a), Sampler(_)) => *a != wgpu::SamplerBindingType::Comparison,
+        (
+            Texture {
+                sample_type: a,
+                view_dimension: a_dim,
+                multisampled: a_multi,
+            },
+            Texture {
+                sample_type: b,
+                view_dimension: b_dim,
+                multisampled: b_multi,
+            },
+        ) => {
+            let same_sample_type = matches!(
+                (a, b),
+                (
+                    wgpu::TextureSampleType::Float { .. },
+                    wgpu::TextureSampleType::Float { .. }
+                ) | (
+                    wgpu::TextureSampleType::Depth,
+                    wgpu::TextureSampleType::Depth
+                ) | (wgpu::TextureSampleType::Sint, wgpu::TextureSampleType::Sint)
+                    | (wgpu::TextureSampleType::Uint, wgpu::TextureSampleType::Uint)
+            );
+            same_sample_type && a_dim == b_dim && a_multi == b_multi
+        }
+        _ => false,
+    }
+}
+
+fn describe_type(ty: &wgpu::BindingType) -> String {
+    match ty {
+        wgpu::BindingType::Buffer { ty, .. } => format!("a {ty:?} buffer"),
+        wgpu::BindingType::Sampler(ty) => format!("a {ty:?} sampler"),
+        wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension,
+            multisampled,
+        } => format!(
+            "a {}{view_dimension:?} {sample_type:?} texture",
+            if *multisampled { "multisampled " } else { "" }
+        ),
+        ty => format!("{ty:?}"),
+    }
+}
+
+fn describe_input(kind: naga::ScalarKind, components: u32) -> String {
+    let scalar = match kind {
+        naga::ScalarKind::Sint => "i32",
+        naga::ScalarKind::Uint => "u32",
+        naga::ScalarKind::Bool => "bool",
+        _ => "f32",
+    };
+    if components == 1 {
+        scalar.to_string()
+    } else {
+        format!("vec{components}<{scalar}>")
+    }
+}
+
+// Scalar kind a vertex format shows up as in the shader
+fn vertex_format_kind(format: wgpu::VertexFormat) -> Option<naga::ScalarKind> {
+    use wgpu::VertexFormat::*;
+    match format {
+        Uint8 | Uint8x2 | Uint8x4 | Uint16 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3
+        | Uint32x4 => Some(naga::ScalarKind::Uint),
+        Sint8 | Sint8x2 | Sint8x4 | Sint16 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3
+        | Sint32x4 => Some(naga::ScalarKind::Sint),
+        // Doubles need a feature and never show up here
+        Float64 | Float64x2 | Float64x3 | Float64x4 => None,
+        // Floats and all the normalized formats
+        _ => Some(naga::ScalarKind::Float),
+    }
+}
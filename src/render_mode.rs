@@ -0,0 +1,256 @@
+use crate::instance::InstanceRaw;
+use crate::preprocessor::Preprocessor;
+use crate::scene::Scene;
+use crate::state::DepthConfig;
+use crate::vert::Vert;
+
+const WIREFRAME_SHADER: &str = "wireframe.wgsl";
+
+// How the scene gets rasterized, everything but Solid is for debugging
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    #[default]
+    Solid,
+    Wireframe,
+    Points,
+    // Solid with the wireframe drawn on top
+    SolidWireframe,
+}
+
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Solid => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::Points,
+            RenderMode::Points => RenderMode::SolidWireframe,
+            RenderMode::SolidWireframe => RenderMode::Solid,
+        }
+    }
+}
+
+// Pipelines for the non solid render modes. Line and point polygon modes
+// aren't available on WebGL2, there the wireframe comes from barycentric
+// coordinates in the shader and points use a point list
+pub struct DebugPipelines {
+    wire_shader: wgpu::ShaderModule,
+    // True when the device can't do POLYGON_MODE_LINE / POLYGON_MODE_POINT
+    barycentric: bool,
+    point_list: bool,
+    wireframe: wgpu::RenderPipeline,
+    // Same as wireframe but drawn over already shaded geometry
+    overlay: wgpu::RenderPipeline,
+    points: wgpu::RenderPipeline,
+}
+
+impl DebugPipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        depth_config: &DepthConfig,
+        sample_count: u32,
+    ) -> Self {
+        let wire_shader = Preprocessor::standard().load_embedded(device, WIREFRAME_SHADER);
+        let features = device.features();
+        let barycentric = !features.contains(wgpu::Features::POLYGON_MODE_LINE);
+        let point_list = !features.contains(wgpu::Features::POLYGON_MODE_POINT);
+        if barycentric {
+            log::info!("Line polygon mode not available, using barycentric wireframe");
+        }
+
+        let (wireframe, overlay, points) = Self::create_pipelines(
+            device,
+            layout,
+            shader,
+            &wire_shader,
+            barycentric,
+            point_list,
+            color_format,
+            depth_config,
+            sample_count,
+        );
+
+        Self {
+            wire_shader,
+            barycentric,
+            point_list,
+            wireframe,
+            overlay,
+            points,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        depth_config: &DepthConfig,
+        sample_count: u32,
+    ) {
+        (self.wireframe, self.overlay, self.points) = Self::create_pipelines(
+            device,
+            layout,
+            shader,
+            &self.wire_shader,
+            self.barycentric,
+            self.point_list,
+            color_format,
+            depth_config,
+            sample_count,
+        );
+    }
+
+    // Swaps in a new wireframe shader, the pipelines need a rebuild after
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replace_shader(&mut self, shader: wgpu::ShaderModule) -> wgpu::ShaderModule {
+        std::mem::replace(&mut self.wire_shader, shader)
+    }
+
+    // Expects the camera and light bind groups to be set already.
+    // Solid modes draw the scene with whatever pipeline is bound
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, mode: RenderMode, scene: &Scene) {
+        match mode {
+            RenderMode::Solid => scene.draw(render_pass),
+            RenderMode::Wireframe => {
+                render_pass.set_pipeline(&self.wireframe);
+                scene.draw_wireframe(render_pass);
+            }
+            RenderMode::Points => {
+                render_pass.set_pipeline(&self.points);
+                if self.point_list {
+                    scene.draw_points(render_pass);
+                } else {
+                    scene.draw(render_pass);
+                }
+            }
+            RenderMode::SolidWireframe => {
+                scene.draw(render_pass);
+                render_pass.set_pipeline(&self.overlay);
+                scene.draw_wireframe(render_pass);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        wire_shader: &wgpu::ShaderModule,
+        barycentric: bool,
+        point_list: bool,
+        color_format: wgpu::TextureFormat,
+        depth_config: &DepthConfig,
+        sample_count: u32,
+    ) -> (
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+    ) {
+        let (wire_entries, wire_primitive) = if barycentric {
+            (
+                ("vs_barycentric", "fs_barycentric"),
+                wgpu::PrimitiveState {
+                    cull_mode: None,
+                    ..Default::default()
+                },
+            )
+        } else {
+            (
+                ("vs_main", "fs_main"),
+                wgpu::PrimitiveState {
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let pipeline = |label: &str,
+                        module: &wgpu::ShaderModule,
+                        (vs_entry, fs_entry): (&str, &str),
+                        primitive: wgpu::PrimitiveState,
+                        depth_write_enabled: bool,
+                        depth_compare: wgpu::CompareFunction| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module,
+                    entry_point: Some(vs_entry),
+                    buffers: &[Vert::desc(), InstanceRaw::desc()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module,
+                    entry_point: Some(fs_entry),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive,
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_config.format,
+                    depth_write_enabled,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let wireframe = pipeline(
+            "Wireframe Pipeline",
+            wire_shader,
+            wire_entries,
+            wire_primitive,
+            true,
+            depth_config.compare,
+        );
+
+        // The lines sit exactly on the solid surface, equal depth has to pass
+        let overlay = pipeline(
+            "Wireframe Overlay Pipeline",
+            wire_shader,
+            wire_entries,
+            wire_primitive,
+            false,
+            depth_config.compare_or_equal(),
+        );
+
+        // Points keep the normal shading
+        let points = pipeline(
+            "Points Pipeline",
+            shader,
+            ("vs_main", "fs_main"),
+            if point_list {
+                wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::PointList,
+                    ..Default::default()
+                }
+            } else {
+                wgpu::PrimitiveState {
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Point,
+                    ..Default::default()
+                }
+            },
+            true,
+            depth_config.compare,
+        );
+
+        (wireframe, overlay, points)
+    }
+}
@@ -0,0 +1,526 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use wgpu::util::DeviceExt;
+
+use crate::instance::{Instance, InstanceBuffer};
+use crate::material::{Material, MaterialLayout, MaterialParams, MaterialTextures};
+use crate::model::{GltfScene, Model};
+use crate::picking::{Hit, PickGeometry, Ray};
+
+// Per object data uploaded to the vertex shader
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ObjectUniform {
+    pub model: [[f32; 4]; 4],
+    // Inverse transpose of the model matrix so normals survive non-uniform scaling
+    pub normal: [[f32; 4]; 4],
+    // Only x is used, the rest pads it out to 16 bytes
+    pub id: [u32; 4],
+}
+
+impl ObjectUniform {
+    pub fn new(transform: Mat4, id: ObjectId) -> Self {
+        Self {
+            model: transform.to_cols_array_2d(),
+            normal: transform.inverse().transpose().to_cols_array_2d(),
+            id: [id.pick_id(), 0, 0, 0],
+        }
+    }
+
+    pub fn bind_desc<'a>() -> wgpu::BindGroupLayoutDescriptor<'a> {
+        wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("object_bind_group_layout"),
+        }
+    }
+}
+
+// Handle to a scene object, stays valid until that object is removed.
+// Slots are never reused so a stale handle can't point at something else
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId(usize);
+
+impl ObjectId {
+    // What the ID pass writes for the object, 0 is left for the background
+    pub fn pick_id(self) -> u32 {
+        self.0 as u32 + 1
+    }
+
+    // Inverse of pick_id
+    pub fn from_pick_id(id: u32) -> Option<Self> {
+        id.checked_sub(1).map(|index| Self(index as usize))
+    }
+}
+
+// One instance of a scene object, what picking selects
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Selection {
+    pub object: ObjectId,
+    pub instance: usize,
+}
+
+// GPU side copy of a Model
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_count: u32,
+    pub vertex_count: u32,
+    // Unindexed copy of the triangles for the barycentric wireframe,
+    // only built when the device can't draw lines with POLYGON_MODE_LINE
+    pub wire_vertex_buffer: Option<wgpu::Buffer>,
+    // CPU copy of the triangles for ray picking
+    pub geometry: PickGeometry,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, model: &Model, label: Option<&str>) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents: bytemuck::cast_slice(&model.verts),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents: bytemuck::cast_slice(&model.indicies),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let wire_vertex_buffer = (!device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE))
+        .then(|| {
+            let verts: Vec<_> = model
+                .indicies
+                .iter()
+                .map(|&i| model.verts[i as usize])
+                .collect();
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label,
+                contents: bytemuck::cast_slice(&verts),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: model.indicies.len() as u32,
+            vertex_count: model.verts.len() as u32,
+            wire_vertex_buffer,
+            geometry: PickGeometry::new(model),
+        }
+    }
+}
+
+pub struct SceneObject {
+    pub mesh: Mesh,
+    // Index into Scene::materials
+    pub material: usize,
+    pub transform: Mat4,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    dirty: bool,
+    // Drawn once per instance, a plain object has a single identity one
+    instances: InstanceBuffer,
+}
+
+// Everything that gets drawn in the main pass
+pub struct Scene {
+    // None where an object was removed
+    objects: Vec<Option<SceneObject>>,
+    pub materials: Vec<Material>,
+    pub material_layout: MaterialLayout,
+    pub object_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl Scene {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            objects: Vec::new(),
+            materials: Vec::new(),
+            material_layout: MaterialLayout::new(device, queue),
+            object_bind_group_layout: device.create_bind_group_layout(&ObjectUniform::bind_desc()),
+        }
+    }
+
+    // Returns the index of the new material
+    pub fn add_material(
+        &mut self,
+        device: &wgpu::Device,
+        params: MaterialParams,
+        textures: MaterialTextures,
+        label: Option<&str>,
+    ) -> usize {
+        let material = self.material_layout.create(device, params, textures, label);
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        mesh: Mesh,
+        material: usize,
+        transform: Mat4,
+    ) -> ObjectId {
+        let id = ObjectId(self.objects.len());
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Object buffer"),
+            contents: bytemuck::cast_slice(&[ObjectUniform::new(transform, id)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.object_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("object_bind_group"),
+        });
+
+        self.objects.push(Some(SceneObject {
+            mesh,
+            material,
+            transform,
+            uniform_buffer,
+            bind_group,
+            dirty: false,
+            instances: InstanceBuffer::new(device, vec![Instance::default()]),
+        }));
+
+        id
+    }
+
+    // Adds every primitive of an imported glTF scene, primitives
+    // without a material use `default_material`
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn add_gltf(
+        &mut self,
+        device: &wgpu::Device,
+        gltf: &GltfScene,
+        default_material: usize,
+    ) -> Vec<ObjectId> {
+        let texture = |index: Option<usize>| index.map(|i| &gltf.textures[i]);
+
+        let first_material = self.materials.len();
+        for material in &gltf.materials {
+            let textures = MaterialTextures {
+                base_color: texture(material.base_color_texture),
+                metallic_roughness: texture(material.metallic_roughness_texture),
+                normal: texture(material.normal_texture),
+                occlusion: texture(material.occlusion_texture),
+                emissive: texture(material.emissive_texture),
+            };
+            self.add_material(
+                device,
+                MaterialParams::from_gltf(material),
+                textures,
+                material.name.as_deref(),
+            );
+        }
+
+        gltf.models
+            .iter()
+            .map(|gltf_model| {
+                let material = gltf_model
+                    .material
+                    .map(|m| first_material + m)
+                    .unwrap_or(default_material);
+
+                let mesh = Mesh::new(device, &gltf_model.model, gltf_model.name.as_deref());
+                self.add(device, mesh, material, gltf_model.transform)
+            })
+            .collect()
+    }
+
+    // Other handles stay valid, the removed one just stops resolving
+    pub fn remove(&mut self, id: ObjectId) -> Option<SceneObject> {
+        self.objects.get_mut(id.0).and_then(Option::take)
+    }
+
+    pub fn get(&self, id: ObjectId) -> Option<&SceneObject> {
+        self.objects.get(id.0).and_then(Option::as_ref)
+    }
+
+    fn get_mut(&mut self, id: ObjectId) -> Option<&mut SceneObject> {
+        self.objects.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    // Every object still in the scene
+    pub fn objects(&self) -> impl Iterator<Item = (ObjectId, &SceneObject)> {
+        self.objects
+            .iter()
+            .enumerate()
+            .filter_map(|(index, object)| Some((ObjectId(index), object.as_ref()?)))
+    }
+
+    // Does nothing for removed objects, so nodes can outlive theirs
+    pub fn set_transform(&mut self, id: ObjectId, transform: Mat4) {
+        if let Some(object) = self.get_mut(id) {
+            object.transform = transform;
+            object.dirty = true;
+        }
+    }
+
+    pub fn instances(&self, id: ObjectId) -> Option<&[Instance]> {
+        self.get(id).map(|object| object.instances.instances())
+    }
+
+    // Returns the index of the new instance within the object
+    #[allow(dead_code)]
+    pub fn add_instance(&mut self, id: ObjectId, instance: Instance) -> Option<usize> {
+        self.get_mut(id)
+            .map(|object| object.instances.push(instance))
+    }
+
+    // False for removed objects and out of range instances
+    #[allow(dead_code)]
+    pub fn set_instance(
+        &mut self,
+        id: ObjectId,
+        instance_index: usize,
+        instance: Instance,
+    ) -> bool {
+        self.get_mut(id)
+            .is_some_and(|object| object.instances.set(instance_index, instance))
+    }
+
+    // Shifts the indices of every instance after it. An object
+    // without any instances left stays in the scene but isn't drawn
+    pub fn remove_instance(&mut self, id: ObjectId, instance_index: usize) -> Option<Instance> {
+        let object = self.get_mut(id)?;
+        (instance_index < object.instances.len() as usize)
+            .then(|| object.instances.remove(instance_index))
+    }
+
+    // Replaces all of them at once
+    pub fn set_instances(&mut self, id: ObjectId, instances: Vec<Instance>) {
+        if let Some(object) = self.get_mut(id) {
+            object.instances.replace(instances);
+        }
+    }
+
+    // Upload any transforms and instances that changed since the last frame
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            let Some(object) = object else {
+                continue;
+            };
+            if object.dirty {
+                queue.write_buffer(
+                    &object.uniform_buffer,
+                    0,
+                    bytemuck::cast_slice(&[ObjectUniform::new(object.transform, ObjectId(index))]),
+                );
+                object.dirty = false;
+            }
+            object.instances.update(device, queue);
+        }
+    }
+
+    // Closest object instance along a world space ray
+    pub fn pick(&self, ray: &Ray) -> Option<Hit> {
+        let instances = self.objects().flat_map(|(id, object)| {
+            object
+                .instances
+                .instances()
+                .iter()
+                .enumerate()
+                .map(move |(instance, data)| (id, instance, object, data.transform))
+        });
+
+        instances
+            .filter_map(|(id, instance, object, instance_transform)| {
+                // Cheaper to move the ray than every vertex
+                let inverse = (object.transform * instance_transform).inverse();
+                let local = ray.transform(inverse);
+                let (distance, triangle) = object.mesh.geometry.raycast(&local)?;
+
+                let [a, b, c] = object.mesh.geometry.triangle(triangle);
+                let normal = inverse
+                    .transpose()
+                    .transform_vector3((b - a).cross(c - a))
+                    .normalize_or_zero();
+
+                Some(Hit {
+                    object: id,
+                    instance,
+                    triangle,
+                    point: ray.at(distance),
+                    normal: if normal.dot(ray.direction) > 0.0 {
+                        -normal
+                    } else {
+                        normal
+                    },
+                    distance,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    // Expects the pipeline and camera bind group to already be set
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        for (_, object) in self.objects() {
+            self.draw_object(render_pass, object);
+        }
+    }
+
+    // Same as draw but the pipeline decides how triangles get rasterized,
+    // the barycentric fallback reads the unindexed copy instead
+    pub fn draw_wireframe(&self, render_pass: &mut wgpu::RenderPass) {
+        for (_, object) in self.objects() {
+            let Some(wire_buffer) = &object.mesh.wire_vertex_buffer else {
+                self.draw_object(render_pass, object);
+                continue;
+            };
+            let Some(instances) = object.instances.slice() else {
+                continue;
+            };
+
+            render_pass.set_bind_group(0, &self.materials[object.material].bind_group, &[]);
+            render_pass.set_bind_group(2, &object.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, wire_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instances);
+            render_pass.draw(0..object.mesh.index_count, 0..object.instances.len());
+        }
+    }
+
+    // Every vertex on its own, for point list pipelines
+    pub fn draw_points(&self, render_pass: &mut wgpu::RenderPass) {
+        for (_, object) in self.objects() {
+            let Some(instances) = object.instances.slice() else {
+                continue;
+            };
+
+            render_pass.set_bind_group(0, &self.materials[object.material].bind_group, &[]);
+            render_pass.set_bind_group(2, &object.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, object.mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instances);
+            render_pass.draw(0..object.mesh.vertex_count, 0..object.instances.len());
+        }
+    }
+
+    fn draw_object(&self, render_pass: &mut wgpu::RenderPass, object: &SceneObject) {
+        let Some(instances) = object.instances.slice() else {
+            return;
+        };
+
+        render_pass.set_bind_group(0, &self.materials[object.material].bind_group, &[]);
+        render_pass.set_bind_group(2, &object.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, object.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instances);
+        render_pass.set_index_buffer(
+            object.mesh.index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        render_pass.draw_indexed(0..object.mesh.index_count, 0, 0..object.instances.len());
+    }
+
+    // Geometry only, for depth passes that bring their own pipeline
+    pub fn draw_depth(&self, render_pass: &mut wgpu::RenderPass, object_group: u32) {
+        for (id, _) in self.objects() {
+            self.draw_object_depth(render_pass, id, object_group);
+        }
+    }
+
+    // Only the selected instance, nothing happens if it's gone
+    pub fn draw_instance_depth(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        selection: Selection,
+        object_group: u32,
+    ) {
+        let Some(object) = self.get(selection.object) else {
+            return;
+        };
+        let Some(instance) = object.instances.instance_slice(selection.instance) else {
+            return;
+        };
+        render_pass.set_bind_group(object_group, &object.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, object.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance);
+        render_pass.set_index_buffer(
+            object.mesh.index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        render_pass.draw_indexed(0..object.mesh.index_count, 0, 0..1);
+    }
+
+    // draw_depth for a single object, nothing happens if it's gone
+    fn draw_object_depth(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        id: ObjectId,
+        object_group: u32,
+    ) {
+        let Some(object) = self.get(id) else {
+            return;
+        };
+        let Some(instances) = object.instances.slice() else {
+            return;
+        };
+        render_pass.set_bind_group(object_group, &object.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, object.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instances);
+        render_pass.set_index_buffer(
+            object.mesh.index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        render_pass.draw_indexed(0..object.mesh.index_count, 0, 0..object.instances.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    // Skips (returns None) on machines without any adapter
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+        pollster::block_on(adapter.request_device(&Default::default(), None)).ok()
+    }
+
+    #[test]
+    fn instances_are_bounds_checked() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let mut scene = Scene::new(&device, &queue);
+        let mesh = Mesh::new(&device, &Model::cube(0.5), None);
+        let id = scene.add(&device, mesh, 0, Mat4::IDENTITY);
+        let moved = Instance::new(Mat4::from_translation(Vec3::X));
+
+        assert_eq!(scene.add_instance(id, moved), Some(1));
+        assert!(scene.set_instance(id, 0, moved));
+        assert!(!scene.set_instance(id, 2, moved));
+        assert!(scene.remove_instance(id, 2).is_none());
+        assert_eq!(scene.instances(id).unwrap().len(), 2);
+        assert!(
+            scene
+                .instances(id)
+                .unwrap()
+                .iter()
+                .all(|instance| instance.transform == moved.transform)
+        );
+
+        // Bigger than the buffer it started with, update has to grow it
+        scene.update(&device, &queue);
+
+        scene.remove(id);
+        assert_eq!(scene.add_instance(id, moved), None);
+        assert!(!scene.set_instance(id, 0, moved));
+    }
+}
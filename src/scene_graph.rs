@@ -0,0 +1,394 @@
+use glam::{Mat4, Quat, Vec3};
+
+use crate::model::GltfScene;
+use crate::scene::{ObjectId, Scene};
+
+// Handle to a node, stays valid until that node is removed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+pub struct Node {
+    #[allow(dead_code)]
+    pub name: String,
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // Scene objects that follow this node
+    pub objects: Vec<ObjectId>,
+    world: Mat4,
+    dirty: bool,
+}
+
+// Read back by whoever owns the hierarchy, the renderer only needs `objects`
+#[allow(dead_code)]
+impl Node {
+    fn new(name: &str, parent: Option<NodeId>) -> Self {
+        Self {
+            name: name.to_string(),
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            parent,
+            children: Vec::new(),
+            objects: Vec::new(),
+            world: Mat4::IDENTITY,
+            dirty: true,
+        }
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> Vec3 {
+        self.scale
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn local_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    // Only up to date after SceneGraph::update
+    pub fn world_matrix(&self) -> Mat4 {
+        self.world
+    }
+}
+
+// Parent/child transform hierarchy, world matrices are only
+// recomputed for nodes (and their children) that changed.
+// Setters do nothing when given a removed node
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Option<Node>>,
+    roots: Vec<NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Some(Node::new(name, parent)));
+
+        match parent.and_then(|parent| self.get_mut(parent)) {
+            Some(parent) => parent.children.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    // Removes the node along with everything below it,
+    // attached scene objects are left for the caller to remove
+    pub fn remove_node(&mut self, id: NodeId) {
+        let Some(node) = self.get(id) else {
+            return;
+        };
+
+        match node.parent {
+            Some(parent) => {
+                if let Some(parent) = self.get_mut(parent) {
+                    parent.children.retain(|c| *c != id);
+                }
+            }
+            None => self.roots.retain(|r| *r != id),
+        }
+
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            if let Some(node) = self.nodes[current.0].take() {
+                stack.extend(node.children);
+            }
+        }
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0).and_then(Option::as_ref)
+    }
+
+    fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    #[allow(dead_code)]
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    // Moves a node under a new parent (or to the root when None),
+    // refuses to create cycles or to use removed nodes
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        let Some(node) = self.get(id) else {
+            return false;
+        };
+        let old_parent = node.parent;
+
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == id {
+                log::warn!("Refusing to parent node {id:?} to its own descendant");
+                return false;
+            }
+            let Some(node) = self.get(current) else {
+                log::warn!("Refusing to parent node {id:?} to removed node {current:?}");
+                return false;
+            };
+            ancestor = node.parent;
+        }
+
+        match old_parent.and_then(|old| self.get_mut(old)) {
+            Some(old) => old.children.retain(|c| *c != id),
+            None => self.roots.retain(|r| *r != id),
+        }
+        match parent.and_then(|new| self.get_mut(new)) {
+            Some(new) => new.children.push(id),
+            None => self.roots.push(id),
+        }
+
+        if let Some(node) = self.get_mut(id) {
+            node.parent = parent;
+            node.dirty = true;
+        }
+        true
+    }
+
+    pub fn set_translation(&mut self, id: NodeId, translation: Vec3) {
+        if let Some(node) = self.get_mut(id) {
+            node.translation = translation;
+            node.dirty = true;
+        }
+    }
+
+    pub fn set_rotation(&mut self, id: NodeId, rotation: Quat) {
+        if let Some(node) = self.get_mut(id) {
+            node.rotation = rotation;
+            node.dirty = true;
+        }
+    }
+
+    pub fn set_scale(&mut self, id: NodeId, scale: Vec3) {
+        if let Some(node) = self.get_mut(id) {
+            node.scale = scale;
+            node.dirty = true;
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn set_local_matrix(&mut self, id: NodeId, matrix: Mat4) {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        if let Some(node) = self.get_mut(id) {
+            node.translation = translation;
+            node.rotation = rotation;
+            node.scale = scale;
+            node.dirty = true;
+        }
+    }
+
+    pub fn attach_object(&mut self, id: NodeId, object: ObjectId) {
+        if let Some(node) = self.get_mut(id) {
+            node.objects.push(object);
+            node.dirty = true;
+        }
+    }
+
+    // Stops the object from following whichever node it was attached to
+    pub fn detach_object(&mut self, object: ObjectId) {
+        for node in self.nodes.iter_mut().flatten() {
+            node.objects.retain(|o| *o != object);
+        }
+    }
+
+    // Recomputes world matrices, returns the nodes that got updated
+    pub fn update(&mut self) -> Vec<NodeId> {
+        let mut updated = Vec::new();
+        let mut stack: Vec<(NodeId, Mat4, bool)> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|root| (*root, Mat4::IDENTITY, false))
+            .collect();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let Some(node) = self.get_mut(id) else {
+                continue;
+            };
+            let changed = parent_changed || node.dirty;
+
+            if changed {
+                node.world = parent_world * node.local_matrix();
+                node.dirty = false;
+                updated.push(id);
+            }
+
+            let world = node.world;
+            for child in node.children.iter().rev() {
+                stack.push((*child, world, changed));
+            }
+        }
+
+        updated
+    }
+
+    // Updates world matrices and pushes them to the attached scene objects
+    pub fn sync(&mut self, scene: &mut Scene) {
+        for id in self.update() {
+            let Some(node) = self.get(id) else {
+                continue;
+            };
+            for object in &node.objects {
+                scene.set_transform(*object, node.world);
+            }
+        }
+    }
+
+    // Depth first, parents are always visited before their children
+    #[allow(dead_code)]
+    pub fn traverse<F: FnMut(NodeId, &Node)>(&self, mut f: F) {
+        let mut stack: Vec<NodeId> = self.roots.iter().rev().copied().collect();
+
+        while let Some(id) = stack.pop() {
+            let Some(node) = self.get(id) else {
+                continue;
+            };
+            f(id, node);
+            stack.extend(node.children.iter().rev());
+        }
+    }
+
+    // Mirrors the glTF node hierarchy under `parent`, objects are attached
+    // using the handles returned from Scene::add_gltf
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn add_gltf(
+        &mut self,
+        gltf: &GltfScene,
+        objects: &[ObjectId],
+        parent: Option<NodeId>,
+    ) -> Vec<NodeId> {
+        let ids: Vec<NodeId> = gltf
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let name = node.name.clone().unwrap_or_else(|| format!("node_{index}"));
+                let id = self.add_node(&name, parent);
+                self.set_local_matrix(id, node.local);
+                id
+            })
+            .collect();
+
+        for (index, node) in gltf.nodes.iter().enumerate() {
+            if let Some(node_parent) = node.parent {
+                self.set_parent(ids[index], Some(ids[node_parent]));
+            }
+        }
+
+        for (gltf_model, object) in gltf.models.iter().zip(objects) {
+            self.attach_object(ids[gltf_model.node], *object);
+        }
+
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visit_order(graph: &SceneGraph) -> Vec<NodeId> {
+        let mut order = Vec::new();
+        graph.traverse(|id, _| order.push(id));
+        order
+    }
+
+    #[test]
+    fn world_matrices_follow_parents() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add_node("parent", None);
+        let child = graph.add_node("child", Some(parent));
+        graph.set_translation(parent, Vec3::X);
+        graph.set_translation(child, Vec3::Y);
+
+        assert_eq!(graph.update(), vec![parent, child]);
+        let world = graph.get(child).unwrap().world_matrix();
+        assert_eq!(world.transform_point3(Vec3::ZERO), Vec3::new(1.0, 1.0, 0.0));
+
+        // Only the moved node and what's below it get recomputed
+        graph.set_translation(child, Vec3::Z);
+        assert_eq!(graph.update(), vec![child]);
+
+        // Local transforms read back the way they were set
+        graph.set_local_matrix(parent, Mat4::from_scale(Vec3::splat(2.0)));
+        let node = graph.get(parent).unwrap();
+        assert_eq!(node.translation(), Vec3::ZERO);
+        assert_eq!(node.rotation(), Quat::IDENTITY);
+        assert_eq!(node.scale(), Vec3::splat(2.0));
+        assert_eq!(node.children(), &[child]);
+    }
+
+    #[test]
+    fn set_parent_refuses_cycles() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node("a", None);
+        let b = graph.add_node("b", Some(a));
+        assert!(!graph.set_parent(a, Some(b)));
+        assert!(graph.set_parent(b, None));
+        assert_eq!(graph.get(b).unwrap().parent(), None);
+        assert!(graph.get(a).unwrap().children().is_empty());
+        assert_eq!(graph.roots(), &[a, b]);
+        assert_eq!(visit_order(&graph), vec![a, b]);
+    }
+
+    #[test]
+    fn removed_nodes_are_ignored() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node("a", None);
+        let b = graph.add_node("b", Some(a));
+        let c = graph.add_node("c", None);
+        graph.remove_node(a);
+
+        assert!(graph.get(a).is_none());
+        assert!(graph.get(b).is_none());
+        assert_eq!(graph.roots(), &[c]);
+        assert_eq!(visit_order(&graph), vec![c]);
+
+        // None of these should panic
+        graph.set_translation(b, Vec3::ONE);
+        graph.set_scale(a, Vec3::ONE);
+        assert!(!graph.set_parent(c, Some(b)));
+        assert!(!graph.set_parent(b, Some(c)));
+        assert_eq!(graph.update(), vec![c]);
+    }
+
+    #[test]
+    fn detached_objects_stop_following() {
+        let first = ObjectId::from_pick_id(1).unwrap();
+        let second = ObjectId::from_pick_id(2).unwrap();
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node("a", None);
+        let b = graph.add_node("b", Some(a));
+        graph.attach_object(a, first);
+        graph.attach_object(b, second);
+        graph.attach_object(b, first);
+
+        graph.detach_object(first);
+        assert!(graph.get(a).unwrap().objects.is_empty());
+        assert_eq!(graph.get(b).unwrap().objects, vec![second]);
+    }
+}
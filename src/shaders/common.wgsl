@@ -0,0 +1,50 @@
+// Types shared by the scene shaders, bindings stay in each shader
+// since the pipelines don't all use the same groups
+
+// Matches Vert::desc()
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) tex_uv: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+};
+
+// Matches InstanceRaw::desc(), steps once per instance
+struct InstanceInput {
+    @location(4) model_0: vec4<f32>,
+    @location(5) model_1: vec4<f32>,
+    @location(6) model_2: vec4<f32>,
+    @location(7) model_3: vec4<f32>,
+    @location(8) normal_0: vec3<f32>,
+    @location(9) normal_1: vec3<f32>,
+    @location(10) normal_2: vec3<f32>,
+    @location(11) tint: vec4<f32>,
+};
+
+fn instance_model(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+}
+
+fn instance_normal(instance: InstanceInput) -> mat3x3<f32> {
+    return mat3x3<f32>(instance.normal_0, instance.normal_1, instance.normal_2);
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) world_pos: vec3<f32>,
+    @location(2) tex_uv: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+};
+
+struct ObjectUniform {
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
+    // x is the ID written by the picking pass
+    id: vec4<u32>,
+};
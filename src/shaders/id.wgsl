@@ -0,0 +1,30 @@
+#include "common.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> object: ObjectUniform;
+
+struct IdOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Object ID and which of its instances
+    @location(0) @interpolate(flat) id: vec2<u32>,
+};
+
+// Object IDs only, for GPU picking
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> IdOutput {
+    var out: IdOutput;
+    out.clip_position = camera.view_proj * object.model * instance_model(instance) * model.position;
+    out.id = vec2<u32>(object.id.x, instance_index);
+    return out;
+}
+
+@fragment
+fn fs_main(in: IdOutput) -> @location(0) vec2<u32> {
+    return in.id;
+}
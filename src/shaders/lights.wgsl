@@ -0,0 +1,23 @@
+// MAX_LIGHTS gets injected by the preprocessor from light.rs
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec4<f32>,    // w is the light kind
+    direction: vec4<f32>,
+    color: vec4<f32>,       // a is the intensity
+    attenuation: vec4<f32>, // constant, linear, quadratic
+    cone: vec4<f32>,        // cos of inner and outer angle
+};
+
+struct LightUniform {
+    lights: array<Light, MAX_LIGHTS>,
+    ambient: vec4<f32>,
+    count: vec4<u32>,
+};
+
+struct ShadowUniform {
+    light_view_proj: mat4x4<f32>,
+    params: vec4<f32>, // bias, filter radius, texel size, casting light index
+};
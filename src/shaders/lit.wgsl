@@ -0,0 +1,113 @@
+#include "common.wgsl"
+#include "lights.wgsl"
+#include "shadowing.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(2) @binding(0)
+var<uniform> object: ObjectUniform;
+@group(3) @binding(0)
+var<uniform> light_data: LightUniform;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Setup output struct
+    var out: VertexOutput;
+
+    // Assign color and texture UVs
+    out.color = model.color * instance.tint;
+    out.tex_uv = model.tex_uv;
+
+    // Lighting is done in world space, instances sit inside the object
+    let world_pos = object.model * instance_model(instance) * model.position;
+    out.world_pos = world_pos.xyz;
+    let normal = instance_normal(instance) * model.normal;
+    out.normal = (object.normal * vec4<f32>(normal, 0.0)).xyz;
+
+    // Clip position adjusted by perspective
+    out.clip_position = camera.view_proj * world_pos;
+    return out;
+}
+
+// Same layout as in pbr.wgsl, only the base color and roughness get used
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    factors: vec4<f32>, // metallic, roughness, normal scale, occlusion strength
+};
+@group(0) @binding(0)
+var<uniform> material: MaterialUniform;
+@group(0) @binding(1)
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(6)
+var s_material: sampler;
+
+const SPECULAR_STRENGTH: f32 = 0.5;
+
+fn blinn_phong(light: Light, normal: vec3<f32>, view_dir: vec3<f32>, world_pos: vec3<f32>, shininess: f32) -> vec3<f32> {
+    let kind = u32(light.position.w);
+
+    var light_dir: vec3<f32>;
+    var falloff = 1.0;
+
+    if kind == LIGHT_DIRECTIONAL {
+        light_dir = -normalize(light.direction.xyz);
+    } else {
+        let to_light = light.position.xyz - world_pos;
+        let dist = length(to_light);
+        light_dir = to_light / dist;
+
+        let att = light.attenuation.xyz;
+        falloff = 1.0 / max(att.x + att.y * dist + att.z * dist * dist, 0.0001);
+
+        if kind == LIGHT_SPOT {
+            // Soft edge between the inner and outer cone
+            let theta = dot(-light_dir, normalize(light.direction.xyz));
+            falloff *= smoothstep(light.cone.y, light.cone.x, theta);
+        }
+    }
+
+    let diffuse = max(dot(normal, light_dir), 0.0);
+
+    let half_dir = normalize(light_dir + view_dir);
+    var specular = 0.0;
+    if diffuse > 0.0 {
+        specular = pow(max(dot(normal, half_dir), 0.0), shininess) * SPECULAR_STRENGTH;
+    }
+
+    return light.color.rgb * light.color.a * (diffuse + specular) * falloff;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base = textureSample(t_base_color, s_material, in.tex_uv) * in.color * material.base_color;
+
+    let normal = normalize(in.normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_pos);
+
+    // Inverse of MaterialParams::from_mtl
+    let roughness = clamp(material.factors.y, 0.04, 1.0);
+    let shininess = max(2.0 / (roughness * roughness) - 2.0, 1.0);
+
+    // Only one light gets a shadow map
+    let caster = i32(shadow.params.w);
+    var shadowed = 1.0;
+    if caster >= 0 {
+        shadowed = shadow_factor(in.world_pos);
+    }
+
+    var lighting = light_data.ambient.rgb;
+    let count = min(light_data.count.x, MAX_LIGHTS);
+    for (var i = 0u; i < count; i++) {
+        var light = blinn_phong(light_data.lights[i], normal, view_dir, in.world_pos, shininess);
+        if i32(i) == caster {
+            light *= shadowed;
+        }
+        lighting += light;
+    }
+
+    return vec4<f32>(base.rgb * lighting + material.emissive.rgb, base.a);
+}
@@ -0,0 +1,73 @@
+// Offset from each pixel to its nearest seed in xy, alpha is 0 where
+// there's none yet
+@group(0) @binding(0)
+var t_seeds: texture_2d<f32>;
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    // rgb and how much of it gets mixed in, 0 turns the tint off
+    tint: vec4<f32>,
+    // width in pixels, jump distance of this pass
+    params: vec4<f32>,
+};
+@group(0) @binding(1)
+var<uniform> outline: OutlineUniform;
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// One jump flood step, keeps the closest of the seeds known `step` pixels away
+@fragment
+fn fs_jump(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_seeds));
+    let pixel = vec2<i32>(position.xy);
+    let step = i32(outline.params.y);
+
+    var best = vec4<f32>(0.0);
+    var best_distance = 1e20;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = pixel + vec2<i32>(x, y) * step;
+            if any(neighbour < vec2<i32>(0)) || any(neighbour >= size) {
+                continue;
+            }
+            let seed = textureLoad(t_seeds, neighbour, 0);
+            let offset = vec2<f32>(neighbour - pixel) + seed.xy;
+            let d = length(offset);
+            if seed.a > 0.0 && d < best_distance {
+                best = vec4<f32>(offset, 0.0, 1.0);
+                best_distance = d;
+            }
+        }
+    }
+    return best;
+}
+
+// Blended over the HDR target, outline around the selection and tint on it
+@fragment
+fn fs_composite(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let seed = textureLoad(t_seeds, vec2<i32>(position.xy), 0);
+    if seed.a <= 0.0 {
+        discard;
+    }
+
+    let d = length(seed.xy);
+    // Covered by the object itself
+    if d < 0.5 {
+        if outline.tint.a <= 0.0 {
+            discard;
+        }
+        return outline.tint;
+    }
+
+    // Last pixel fades out so the edge isn't jagged
+    let coverage = clamp(outline.params.x - d + 1.0, 0.0, 1.0);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(outline.color.rgb, outline.color.a * coverage);
+}
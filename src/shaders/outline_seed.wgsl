@@ -0,0 +1,22 @@
+#include "common.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> object: ObjectUniform;
+
+// Every pixel the selected instance covers is its own seed, the
+// jump flood then spreads the nearest one to the pixels around it
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * object.model * instance_model(instance) * model.position;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    // No offset, found
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
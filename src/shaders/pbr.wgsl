@@ -0,0 +1,179 @@
+#include "common.wgsl"
+#include "lights.wgsl"
+#include "shadowing.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(2) @binding(0)
+var<uniform> object: ObjectUniform;
+@group(3) @binding(0)
+var<uniform> light_data: LightUniform;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Setup output struct
+    var out: VertexOutput;
+
+    // Assign color and texture UVs
+    out.color = model.color * instance.tint;
+    out.tex_uv = model.tex_uv;
+
+    // Lighting is done in world space, instances sit inside the object
+    let world_pos = object.model * instance_model(instance) * model.position;
+    out.world_pos = world_pos.xyz;
+    let normal = instance_normal(instance) * model.normal;
+    out.normal = (object.normal * vec4<f32>(normal, 0.0)).xyz;
+
+    // Clip position adjusted by perspective
+    out.clip_position = camera.view_proj * world_pos;
+    return out;
+}
+
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    factors: vec4<f32>, // metallic, roughness, normal scale, occlusion strength
+};
+@group(0) @binding(0)
+var<uniform> material: MaterialUniform;
+@group(0) @binding(1)
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(2)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(4)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(5)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(6)
+var s_material: sampler;
+
+const PI: f32 = 3.14159265359;
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with Schlick-GGX for both view and light
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = (r * r) / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Incoming direction and radiance of a light at `world_pos`
+fn light_radiance(light: Light, world_pos: vec3<f32>, light_dir: ptr<function, vec3<f32>>) -> vec3<f32> {
+    let kind = u32(light.position.w);
+    var falloff = 1.0;
+
+    if kind == LIGHT_DIRECTIONAL {
+        *light_dir = -normalize(light.direction.xyz);
+    } else {
+        let to_light = light.position.xyz - world_pos;
+        let dist = length(to_light);
+        *light_dir = to_light / dist;
+
+        let att = light.attenuation.xyz;
+        falloff = 1.0 / max(att.x + att.y * dist + att.z * dist * dist, 0.0001);
+
+        if kind == LIGHT_SPOT {
+            // Soft edge between the inner and outer cone
+            let theta = dot(-*light_dir, normalize(light.direction.xyz));
+            falloff *= smoothstep(light.cone.y, light.cone.x, theta);
+        }
+    }
+
+    return light.color.rgb * light.color.a * falloff;
+}
+
+// Builds a tangent frame from screen space derivatives since
+// our vertices don't carry tangents
+fn perturb_normal(normal: vec3<f32>, world_pos: vec3<f32>, uv: vec2<f32>, sample: vec3<f32>) -> vec3<f32> {
+    let dp1 = dpdx(world_pos);
+    let dp2 = dpdy(world_pos);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+
+    let dp2perp = cross(dp2, normal);
+    let dp1perp = cross(normal, dp1);
+    let t = dp2perp * duv1.x + dp1perp * duv2.x;
+    let b = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    let inv_max = inverseSqrt(max(dot(t, t), dot(b, b)));
+    if inv_max > 1e6 {
+        // Degenerate UVs, nothing sensible to do
+        return normal;
+    }
+
+    let tbn = mat3x3<f32>(t * inv_max, b * inv_max, normal);
+    return normalize(tbn * sample);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base_color = textureSample(t_base_color, s_material, in.tex_uv) * in.color * material.base_color;
+    let mr = textureSample(t_metallic_roughness, s_material, in.tex_uv);
+    let normal_sample = textureSample(t_normal, s_material, in.tex_uv).xyz;
+    let occlusion_sample = textureSample(t_occlusion, s_material, in.tex_uv).r;
+    let emissive = textureSample(t_emissive, s_material, in.tex_uv).rgb * material.emissive.rgb;
+
+    // glTF keeps roughness in green and metallic in blue
+    let metallic = clamp(material.factors.x * mr.b, 0.0, 1.0);
+    let roughness = clamp(material.factors.y * mr.g, 0.04, 1.0);
+    let occlusion = mix(1.0, occlusion_sample, material.factors.w);
+
+    let scaled_normal = (normal_sample * 2.0 - 1.0) * vec3<f32>(material.factors.z, material.factors.z, 1.0);
+    let n = perturb_normal(normalize(in.normal), in.world_pos, in.tex_uv, normalize(scaled_normal));
+    let v = normalize(camera.view_pos.xyz - in.world_pos);
+    let n_dot_v = max(dot(n, v), 0.0001);
+
+    // Dielectrics reflect about 4%, metals tint reflections with their color
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+
+    // Only one light gets a shadow map
+    let caster = i32(shadow.params.w);
+    var shadowed = 1.0;
+    if caster >= 0 {
+        shadowed = shadow_factor(in.world_pos);
+    }
+
+    var lo = vec3<f32>(0.0);
+    let count = min(light_data.count.x, MAX_LIGHTS);
+    for (var i = 0u; i < count; i++) {
+        var l: vec3<f32>;
+        var radiance = light_radiance(light_data.lights[i], in.world_pos, &l);
+        if i32(i) == caster {
+            radiance *= shadowed;
+        }
+
+        let h = normalize(v + l);
+        let n_dot_l = max(dot(n, l), 0.0);
+        let n_dot_h = max(dot(n, h), 0.0);
+
+        let d = distribution_ggx(n_dot_h, roughness);
+        let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+
+        let specular = (d * g * f) / (4.0 * n_dot_v * n_dot_l + 0.0001);
+        let k_d = (vec3<f32>(1.0) - f) * (1.0 - metallic);
+
+        lo += (k_d * base_color.rgb / PI + specular) * radiance * n_dot_l;
+    }
+
+    let ambient = light_data.ambient.rgb * base_color.rgb * occlusion;
+    return vec4<f32>(ambient + lo + emissive, base_color.a);
+}
@@ -0,0 +1,16 @@
+#include "common.wgsl"
+#include "lights.wgsl"
+
+@group(0) @binding(0)
+var<uniform> shadow: ShadowUniform;
+@group(1) @binding(0)
+var<uniform> object: ObjectUniform;
+
+// Depth only, no fragment stage needed
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return shadow.light_view_proj * object.model * instance_model(instance) * model.position;
+}
@@ -0,0 +1,35 @@
+// Shadow map lookups for the lit scene shaders, the bindings sit next
+// to the lights in group 3. Needs lights.wgsl for ShadowUniform
+@group(3) @binding(1)
+var<uniform> shadow: ShadowUniform;
+@group(3) @binding(2)
+var t_shadow: texture_depth_2d;
+@group(3) @binding(3)
+var s_shadow: sampler_comparison;
+
+// 1.0 is fully lit, PCF over a (2r + 1)^2 texel area
+fn shadow_factor(world_pos: vec3<f32>) -> f32 {
+    let light_pos = shadow.light_view_proj * vec4<f32>(world_pos, 1.0);
+    let ndc = light_pos.xyz / light_pos.w;
+
+    // Texture space has y pointing down
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, -ndc.y * 0.5 + 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let depth = ndc.z - shadow.params.x;
+    let radius = i32(shadow.params.y);
+    let texel = shadow.params.z;
+
+    var lit = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, depth);
+        }
+    }
+
+    let taps = f32((2 * radius + 1) * (2 * radius + 1));
+    return lit / taps;
+}
@@ -1,25 +0,0 @@
-struct VertexInput {
-    @location(0) position: vec4<f32>,
-    @location(1) color: vec4<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec4<f32>,
-};
-
-@vertex
-fn vs_main(
-    model: VertexInput
-) -> VertexOutput {
-    // Setup output struct
-    var out: VertexOutput;
-    out.color = model.color;
-    out.clip_position = model.position;
-    return out;
-}
-
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
-}
@@ -0,0 +1,37 @@
+struct SkyboxUniform {
+    inv_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> skybox: SkyboxUniform;
+@group(0) @binding(1)
+var t_skybox: texture_cube<f32>;
+@group(0) @binding(2)
+var s_skybox: sampler;
+
+// 0 with reverse Z, set when the pipeline gets created
+override FAR_DEPTH: f32 = 1.0;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// One triangle that covers the whole screen, sitting on the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, FAR_DEPTH, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The view has no translation so the unprojected point is the view direction
+    let world = skybox.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize(world.xyz / world.w);
+    return textureSample(t_skybox, s_skybox, dir);
+}
@@ -0,0 +1,92 @@
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+
+struct TonemapUniform {
+    // exposure multiplier, operator id
+    params: vec4<f32>,
+};
+@group(0) @binding(1)
+var<uniform> tonemap: TonemapUniform;
+
+const REINHARD: u32 = 0u;
+const ACES_FILMIC: u32 = 1u;
+const AGX: u32 = 2u;
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces_filmic(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Polynomial approximation of the AgX base contrast curve
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2
+        - 40.14 * x4 * x
+        + 31.96 * x4
+        - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    // Log encode into the AgX working space
+    var x = inset * color;
+    x = clamp(log2(max(x, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    x = (x - min_ev) / (max_ev - min_ev);
+    x = agx_contrast(x);
+
+    // Back out and undo the 2.2 display encoding, the surface does sRGB
+    x = outset * x;
+    return pow(max(x, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(t_hdr, vec2<i32>(position.xy), 0);
+    let color = max(hdr.rgb, vec3<f32>(0.0)) * tonemap.params.x;
+
+    var mapped: vec3<f32>;
+    switch u32(tonemap.params.y) {
+        case REINHARD: {
+            mapped = reinhard(color);
+        }
+        case AGX: {
+            mapped = agx(color);
+        }
+        default: {
+            mapped = aces_filmic(color);
+        }
+    }
+    return vec4<f32>(mapped, 1.0);
+}
@@ -0,0 +1,60 @@
+#include "common.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(2) @binding(0)
+var<uniform> object: ObjectUniform;
+
+const WIRE_COLOR: vec3<f32> = vec3<f32>(0.1, 1.0, 0.4);
+// Line width in pixels for the barycentric version
+const WIRE_WIDTH: f32 = 1.0;
+
+struct WireOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) barycentric: vec3<f32>,
+};
+
+// Used with PolygonMode::Line, the rasterizer draws the edges
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * object.model * instance_model(instance) * model.position;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(WIRE_COLOR, 1.0);
+}
+
+// Fallback for when line polygon mode isn't available (WebGL2).
+// Expects unindexed triangles so every vertex knows its corner
+@vertex
+fn vs_barycentric(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(vertex_index) index: u32,
+) -> WireOutput {
+    var out: WireOutput;
+    out.clip_position = camera.view_proj * object.model * instance_model(instance) * model.position;
+
+    let corner = index % 3u;
+    out.barycentric = vec3<f32>(
+        f32(corner == 0u),
+        f32(corner == 1u),
+        f32(corner == 2u),
+    );
+    return out;
+}
+
+@fragment
+fn fs_barycentric(in: WireOutput) -> @location(0) vec4<f32> {
+    // Distance to the closest edge in pixels
+    let edge = in.barycentric / fwidth(in.barycentric);
+    let dist = min(edge.x, min(edge.y, edge.z));
+    if dist > WIRE_WIDTH {
+        discard;
+    }
+    return vec4<f32>(WIRE_COLOR, 1.0);
+}
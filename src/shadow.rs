@@ -0,0 +1,273 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::instance::InstanceRaw;
+use crate::preprocessor::Preprocessor;
+use crate::reflect::{ReflectError, ShaderReflection};
+use crate::scene::{ObjectUniform, Scene};
+use crate::texture::Texture;
+use crate::vert::Vert;
+
+const SHADOW_SHADER: &str = "shadow.wgsl";
+
+// Settings for the directional light's shadow map
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowConfig {
+    // Width and height of the shadow map in texels
+    pub resolution: u32,
+    // Depth offset applied when comparing, fights shadow acne
+    pub bias: f32,
+    // PCF kernel radius in texels, 0 takes a single sample
+    pub filter_radius: u32,
+    // Half size of the area around the camera target that gets shadows
+    pub extent: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            bias: 0.002,
+            filter_radius: 1,
+            extent: 6.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ShadowUniform {
+    pub light_view_proj: [[f32; 4]; 4],
+    // bias, filter radius, texel size, index of the casting light (-1 for none)
+    pub params: [f32; 4],
+}
+
+impl ShadowUniform {
+    fn new(light_view_proj: Mat4, config: &ShadowConfig, caster: Option<usize>) -> Self {
+        Self {
+            light_view_proj: light_view_proj.to_cols_array_2d(),
+            params: [
+                config.bias,
+                config.filter_radius as f32,
+                1.0 / config.resolution as f32,
+                caster.map(|c| c as f32).unwrap_or(-1.0),
+            ],
+        }
+    }
+}
+
+pub struct ShadowMap {
+    pub config: ShadowConfig,
+    pub texture: Texture,
+    pub buffer: wgpu::Buffer,
+    pass_bind_group: wgpu::BindGroup,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pipeline_layout: wgpu::PipelineLayout,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    shader: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    pub fn new(
+        device: &wgpu::Device,
+        object_layout: &wgpu::BindGroupLayout,
+        config: ShadowConfig,
+    ) -> Result<Self, ReflectError> {
+        let config = Self::clamp_config(device, config);
+        let texture = Self::create_texture(device, &config);
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform::new(Mat4::IDENTITY, &config, None)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // The shadow pass only needs the light's matrix
+        let pass_layout_desc = wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shadow_pass_bind_group_layout"),
+        };
+        ShaderReflection::embedded(SHADOW_SHADER).and_then(|reflection| {
+            reflection.validate(
+                &[pass_layout_desc.clone(), ObjectUniform::bind_desc()],
+                &[Vert::desc(), InstanceRaw::desc()],
+            )
+        })?;
+        let pass_layout = device.create_bind_group_layout(&pass_layout_desc);
+
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pass_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("shadow_pass_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&pass_layout, object_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = Preprocessor::standard().load_embedded(device, SHADOW_SHADER);
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, &shader);
+
+        Ok(Self {
+            config,
+            texture,
+            buffer,
+            pass_bind_group,
+            pipeline_layout,
+            shader,
+            pipeline,
+        })
+    }
+
+    // Swaps in a new shader, the pipeline needs a rebuild after
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replace_shader(&mut self, shader: wgpu::ShaderModule) -> wgpu::ShaderModule {
+        std::mem::replace(&mut self.shader, shader)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.pipeline_layout, &self.shader);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        // Depth only, slope scaled bias takes care of most of the acne
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Vert::desc(), InstanceRaw::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Returns true when the texture got recreated, anything
+    // holding its view (the light bind group) has to be rebuilt
+    pub fn set_config(&mut self, device: &wgpu::Device, config: ShadowConfig) -> bool {
+        let config = Self::clamp_config(device, config);
+        let resized = config.resolution != self.config.resolution;
+        self.config = config;
+
+        if resized {
+            self.texture = Self::create_texture(device, &self.config);
+        }
+        resized
+    }
+
+    // Fits the light's orthographic view around `center`
+    pub fn update(&self, queue: &wgpu::Queue, caster: Option<(usize, Vec3)>, center: Vec3) {
+        let extent = self.config.extent;
+        let light_view_proj = match caster {
+            Some((_, direction)) => {
+                let direction = direction.normalize();
+                // look_at breaks down when looking straight along up
+                let up = if direction.y.abs() > 0.99 {
+                    Vec3::Z
+                } else {
+                    Vec3::Y
+                };
+                let eye = center - direction * extent * 2.0;
+                let view = Mat4::look_at_rh(eye, center, up);
+                let proj =
+                    Mat4::orthographic_rh(-extent, extent, -extent, extent, 0.1, extent * 4.0);
+                proj * view
+            }
+            None => Mat4::IDENTITY,
+        };
+
+        let uniform = ShadowUniform::new(light_view_proj, &self.config, caster.map(|c| c.0));
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, scene: &Scene) {
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        shadow_pass.set_pipeline(&self.pipeline);
+        shadow_pass.set_bind_group(0, &self.pass_bind_group, &[]);
+        scene.draw_depth(&mut shadow_pass, 1);
+    }
+
+    fn clamp_config(device: &wgpu::Device, mut config: ShadowConfig) -> ShadowConfig {
+        // WebGL2 only guarantees 2048x2048 textures
+        let max = device.limits().max_texture_dimension_2d;
+        if config.resolution > max {
+            log::warn!(
+                "Shadow map resolution {} is over the limit, using {max}",
+                config.resolution
+            );
+        }
+        config.resolution = config.resolution.clamp(1, max);
+        config
+    }
+
+    fn create_texture(device: &wgpu::Device, config: &ShadowConfig) -> Texture {
+        Texture::create_depth_texture(
+            device,
+            config.resolution,
+            config.resolution,
+            Texture::DEPTH_FORMAT,
+            1,
+            Some("shadow_map"),
+        )
+    }
+}
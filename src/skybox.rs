@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Mat4, Vec3};
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, DEFAULT_FOVY, Projection};
+use crate::preprocessor::Preprocessor;
+use crate::reflect::{ReflectError, ShaderReflection};
+use crate::state::DepthConfig;
+use crate::texture::Texture;
+
+const SKYBOX_SHADER: &str = "skybox.wgsl";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SkyboxUniform {
+    // Inverse view projection without the camera's translation
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+pub struct Skybox {
+    pub texture: Texture,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        texture: Texture,
+        color_format: wgpu::TextureFormat,
+        depth_config: &DepthConfig,
+        sample_count: u32,
+    ) -> Result<Self, ReflectError> {
+        ShaderReflection::embedded(SKYBOX_SHADER)
+            .and_then(|reflection| reflection.validate(&[Self::bind_desc()], &[]))?;
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox buffer"),
+            contents: bytemuck::cast_slice(&[SkyboxUniform {
+                inv_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&Self::bind_desc());
+
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer, &texture);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = Preprocessor::standard().load_embedded(device, SKYBOX_SHADER);
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth_config,
+            sample_count,
+        );
+
+        Ok(Self {
+            texture,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline_layout,
+            shader,
+            pipeline,
+        })
+    }
+
+    // Vertical gradient used when no cubemap is loaded
+    pub fn gradient_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        zenith: Vec3,
+        horizon: Vec3,
+        ground: Vec3,
+    ) -> Texture {
+        const SIZE: u32 = 64;
+
+        let faces: [DynamicImage; 6] = std::array::from_fn(|face| {
+            let img = RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+                let u = (x as f32 + 0.5) / SIZE as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / SIZE as f32 * 2.0 - 1.0;
+
+                // Direction through this texel, same face order as cube_from_images
+                let dir = match face {
+                    0 => Vec3::new(1.0, -v, -u),
+                    1 => Vec3::new(-1.0, -v, u),
+                    2 => Vec3::new(u, 1.0, v),
+                    3 => Vec3::new(u, -1.0, -v),
+                    4 => Vec3::new(u, -v, 1.0),
+                    _ => Vec3::new(-u, -v, -1.0),
+                }
+                .normalize();
+
+                let color = if dir.y >= 0.0 {
+                    horizon.lerp(zenith, dir.y.powf(0.5))
+                } else {
+                    horizon.lerp(ground, (-dir.y).powf(0.3))
+                };
+
+                let [r, g, b] = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+                    .to_array()
+                    .map(|c| c as u8);
+                Rgba([r, g, b, 255])
+            });
+            DynamicImage::ImageRgba8(img)
+        });
+
+        // Faces are all the same size so this can't fail
+        Texture::cube_from_images(device, queue, &faces, Some("skybox_gradient")).unwrap()
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn set_texture(&mut self, device: &wgpu::Device, texture: Texture) {
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.buffer, &texture);
+        self.texture = texture;
+    }
+
+    // Swaps in a new shader, the pipeline needs a rebuild after
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replace_shader(&mut self, shader: wgpu::ShaderModule) -> wgpu::ShaderModule {
+        std::mem::replace(&mut self.shader, shader)
+    }
+
+    // Needed when the target format, depth settings or sample count change
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_config: &DepthConfig,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            color_format,
+            depth_config,
+            sample_count,
+        );
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        // Only keep the rotation so the sky never gets closer
+        let view = Mat4::look_at_rh(camera.eye, camera.target, camera.up);
+        let view_rot = Mat4::from_mat3(Mat3::from_mat4(view));
+        // This only turns pixels into directions, so it stays a plain finite
+        // perspective whatever the camera uses. An orthographic sky would
+        // be one flat color, use a regular field of view for that
+        let fovy = match camera.projection {
+            Projection::Perspective { fovy } => fovy,
+            Projection::Orthographic { .. } => DEFAULT_FOVY,
+        };
+        let proj = Mat4::perspective_rh(fovy, camera.aspect, camera.znear, camera.zfar);
+
+        let uniform = SkyboxUniform {
+            inv_view_proj: (proj * view_rot).inverse().to_cols_array_2d(),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Draw after the scene so only uncovered pixels get shaded
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn bind_desc<'a>() -> wgpu::BindGroupLayoutDescriptor<'a> {
+        wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        depth_config: &DepthConfig,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        // Fullscreen triangle at the far plane, depth tested but never written.
+        // Equal has to pass as well since the sky sits exactly at the clear value
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &HashMap::from([(
+                        "FAR_DEPTH".to_string(),
+                        depth_config.far_depth() as f64,
+                    )]),
+                    ..Default::default()
+                },
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_config.format,
+                depth_write_enabled: false,
+                depth_compare: depth_config.compare_or_equal(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
@@ -1,44 +1,199 @@
-use std::{iter, sync::Arc};
+use std::{f32::consts, iter, sync::Arc};
+
+#[cfg(not(target_arch = "wasm32"))]
+use pollster::FutureExt;
 use wgpu::util::DeviceExt;
-use winit::{dpi::PhysicalSize, window::Window};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    window::{CursorGrabMode, Window},
+};
+
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
 
-use crate::camera::{Camera, CameraController, CameraUniform};
-use crate::model::Model;
+use crate::camera::{Camera, CameraController, CameraMode, CameraUniform, ViewPreset};
+use crate::hdr::{EXPOSURE_STEP, HdrTarget, TonemapConfig, hdr_format};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::{self, ShaderWatcher};
+use crate::id_buffer::IdBuffer;
+use crate::instance::{Instance, InstanceRaw};
+use crate::light::{Light, LightUniform, Lights};
+use crate::material::{MaterialLayout, MaterialParams, MaterialTextures};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::GltfError;
+use crate::model::{GltfScene, Model};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::obj::ObjError;
+use crate::outline::{Outline, OutlineConfig};
+use crate::picking::{Hit, PickMode};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::preprocessor::PreprocessError;
+use crate::preprocessor::Preprocessor;
+use crate::reflect::{ReflectError, ShaderReflection};
+use crate::render_mode::{DebugPipelines, RenderMode};
+use crate::scene::{Mesh, ObjectId, ObjectUniform, Scene, Selection};
+use crate::scene_graph::{NodeId, SceneGraph};
+use crate::shadow::{ShadowConfig, ShadowMap};
+use crate::skybox::Skybox;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::texture::CubemapError;
 use crate::texture::Texture;
+use crate::timing::{FixedTimestep, Timestep};
 use crate::vert::Vert;
-// Shader code
-// TODO: Make it so that we can load this from a file instead
-// of just including it
-const WGSL_CODE: wgpu::ShaderModuleDescriptor<'static> =
-    wgpu::include_wgsl!("shaders/simple_texture.wgsl");
+use crate::viewport::{SceneTargets, Viewport, ViewportDependent};
+// Shader code, embedded so the binary works on its own.
+// See hot_reload.rs for loading it from disk while developing
+const PBR_SHADER: &str = "pbr.wgsl";
+const LIT_SHADER: &str = "lit.wgsl";
+// Shaders drawn with the main pipeline layout
+const SCENE_SHADERS: &[&str] = &[PBR_SHADER, LIT_SHADER, "wireframe.wgsl"];
+// The ones hot reloading knows how to swap in
+#[cfg(not(target_arch = "wasm32"))]
+const RELOADABLE_SHADERS: &[&str] = &[
+    PBR_SHADER,
+    LIT_SHADER,
+    "wireframe.wgsl",
+    "skybox.wgsl",
+    "shadow.wgsl",
+    "tonemap.wgsl",
+];
+// Simulation rate when the fixed timestep is on. Low on purpose,
+// interpolation is what keeps it smooth
+const FIXED_UPDATE_RATE: f32 = 30.0;
+
+// Which shader the solid render mode lights the scene with
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ShadingModel {
+    // Cook-Torrance with the full glTF material
+    #[default]
+    Pbr,
+    // Plain Blinn-Phong, only looks at the base color and roughness
+    #[allow(dead_code)]
+    BlinnPhong,
+}
+
+impl ShadingModel {
+    fn shader(self) -> &'static str {
+        match self {
+            ShadingModel::Pbr => PBR_SHADER,
+            ShadingModel::BlinnPhong => LIT_SHADER,
+        }
+    }
+}
+
+// Depth buffer settings for the render pipeline
+#[derive(Copy, Clone, Debug)]
+pub struct DepthConfig {
+    pub format: wgpu::TextureFormat,
+    pub compare: wgpu::CompareFunction,
+}
+
+impl DepthConfig {
+    // Reversed depth, near is 1 and far 0. Goes with Camera::reverse_z,
+    // floats have most of their precision near 0 so that evens things out
+    pub fn reversed() -> Self {
+        Self {
+            format: Texture::DEPTH_FORMAT,
+            compare: wgpu::CompareFunction::Greater,
+        }
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        matches!(
+            self.compare,
+            wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual
+        )
+    }
+
+    // Depth at the far plane, what the buffer gets cleared to
+    pub fn far_depth(&self) -> f32 {
+        if self.is_reversed() { 0.0 } else { 1.0 }
+    }
+
+    // For things drawn at exactly the depth already in the buffer
+    pub fn compare_or_equal(&self) -> wgpu::CompareFunction {
+        match self.compare {
+            wgpu::CompareFunction::Less => wgpu::CompareFunction::LessEqual,
+            wgpu::CompareFunction::Greater => wgpu::CompareFunction::GreaterEqual,
+            compare => compare,
+        }
+    }
+}
+
+impl Default for DepthConfig {
+    fn default() -> Self {
+        Self {
+            format: Texture::DEPTH_FORMAT,
+            compare: wgpu::CompareFunction::Less,
+        }
+    }
+}
 
 // Program state
 pub struct State {
     // General fields needed for WGPU to work
     pub surface: wgpu::Surface<'static>,
     pub surface_format: wgpu::TextureFormat,
+    pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     // pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub window: Arc<Window>,
     pub render_pipeline: wgpu::RenderPipeline,
-    // Buffers & Bindgroups
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub diffuse_bind_group: wgpu::BindGroup,
+    pub render_pipeline_layout: wgpu::PipelineLayout,
+    pub shader: wgpu::ShaderModule,
+    pub shading_model: ShadingModel,
+    // Solid, wireframe or points
+    pub render_mode: RenderMode,
+    pub debug_pipelines: DebugPipelines,
+    // Depth buffer and MSAA settings, the targets follow them
+    pub depth_config: DepthConfig,
+    pub sample_count: u32,
+    pub targets: SceneTargets,
+    // Scene gets rendered here in HDR, then tonemapped to the surface
+    pub hdr: HdrTarget,
+    pub hdr_format: wgpu::TextureFormat,
+    // Index of the material in `scene` using the test texture
+    pub default_material: usize,
     // Camera
     pub camera: Camera,
     pub camera_uniform: CameraUniform,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     pub camera_controller: CameraController,
-    pub model: Model,
+    // Set for a fixed simulation rate, `camera` is then the latest step
+    // and `previous_camera` the one before it
+    fixed_timestep: Option<FixedTimestep>,
+    previous_camera: Camera,
+    // What the last frame was drawn with, in between the two above when
+    // the fixed timestep is on. Picking goes by this one
+    rendered_camera: Camera,
+    pub pick_mode: PickMode,
+    // Object instance last clicked on, `hit` has the details
+    // when it came from a ray pick
+    pub selected: Option<Selection>,
+    pub hit: Option<Hit>,
+    pub id_buffer: IdBuffer,
+    // Highlights `selected`
+    pub outline: Outline,
+    // Objects to draw and the hierarchy that places them
+    pub scene: Scene,
+    pub scene_graph: SceneGraph,
+    // Instancing demo, toggled on and off
+    cube_grid: Option<(NodeId, ObjectId)>,
+    pub lights: Lights,
+    pub shadow_map: ShadowMap,
+    // Drawn behind everything, fills whatever the scene doesn't cover
+    pub skybox: Skybox,
+    // Only there when shader hot reloading is turned on
+    #[cfg(not(target_arch = "wasm32"))]
+    pub shader_watcher: Option<ShaderWatcher>,
 }
 
 impl State {
-    // Creating some of the wgpu types requires async code
-    pub async fn new(window: Window) -> State {
+    // Creating some of the wgpu types requires async code, fails
+    // if one of the embedded shaders doesn't match its layouts
+    pub async fn new(window: Window) -> Result<State, ReflectError> {
         let size = window.inner_size();
         let window = Arc::new(window);
 
@@ -61,8 +216,12 @@ impl State {
             .unwrap();
 
         // Render device
-        let desktop_features =
-            wgpu::Features::POLYGON_MODE_POINT | wgpu::Features::POLYGON_MODE_LINE;
+        // Adapter specific format features unlock sample counts other than 1 and 4
+        // Polygon modes are only for the debug views, those fall back when missing
+        let desktop_features = adapter.features()
+            & (wgpu::Features::POLYGON_MODE_POINT
+                | wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
@@ -84,31 +243,6 @@ impl State {
             .await
             .unwrap();
 
-        // Texture
-        let tex1_bytes = include_bytes!("res/texture_test_1.png");
-        let img: image::DynamicImage = image::load_from_memory(tex1_bytes).unwrap();
-        let diffuse_texture =
-            Texture::from_image(&device, &queue, &img, Some("diffuse_texture")).unwrap();
-
-        let texture_bind_group_layout = device.create_bind_group_layout(
-            &diffuse_texture.bind_desc(Some("texture_bind_group_layout")),
-        );
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
         // Camera
         let camera = Camera::new(size.width as f32 / size.height as f32);
         let mut camera_uniform = CameraUniform::new();
@@ -131,40 +265,143 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let camera_controller = CameraController::new(0.02);
+        // Units and radians per second
+        let camera_controller = CameraController::new(1.5, 1.5);
 
-        // Shader and render pipeline
-        let shader = device.create_shader_module(WGSL_CODE);
+        // Shader and render pipeline, checked against the layouts
+        // first so a mismatch gets a readable error instead of a wgpu one
+        for name in SCENE_SHADERS {
+            ShaderReflection::embedded(name)
+                .and_then(|reflection| check_scene_shader(&reflection))?;
+        }
+        let shading_model = ShadingModel::default();
+        let shader = Preprocessor::standard().load_embedded(&device, shading_model.shader());
 
-        let model = Model::cube(0.5);
+        // Scene, a few cubes so there is more than one thing to look at
+        // the small one sits on top of the first and follows it around
+        let mut scene = Scene::new(&device, &queue);
+        let mut scene_graph = SceneGraph::new();
+        let cube = Model::cube(0.5);
 
-        // Buffers
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&model.verts), //VERTS),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+        let tex1_bytes = include_bytes!("res/texture_test_1.png");
+        let img: image::DynamicImage = image::load_from_memory(tex1_bytes).unwrap();
+        let diffuse_texture =
+            Texture::from_image(&device, &queue, &img, Some("diffuse_texture")).unwrap();
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&model.indicies), //INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let default_material = scene.add_material(
+            &device,
+            MaterialParams::default(),
+            MaterialTextures {
+                base_color: Some(&diffuse_texture),
+                ..Default::default()
+            },
+            Some("default_material"),
+        );
+        // A shiny metal one to show off the lighting
+        let metal_material = scene.add_material(
+            &device,
+            MaterialParams {
+                metallic: 1.0,
+                roughness: 0.25,
+                ..Default::default()
+            },
+            MaterialTextures {
+                base_color: Some(&diffuse_texture),
+                ..Default::default()
+            },
+            Some("metal_material"),
+        );
+
+        // Ground plane to catch the shadows
+        let ground = scene_graph.add_node("ground", None);
+        let ground_mesh = Mesh::new(&device, &Model::square(1.0), Some("ground"));
+        let ground_object = scene.add(&device, ground_mesh, default_material, Mat4::IDENTITY);
+        scene_graph.attach_object(ground, ground_object);
+        scene_graph.set_translation(ground, Vec3::new(0.0, -0.5, -1.0));
+        scene_graph.set_rotation(ground, Quat::from_rotation_x(-consts::FRAC_PI_2));
+        scene_graph.set_scale(ground, Vec3::splat(4.0));
+
+        let mut add_cube = |name: &str, parent: Option<NodeId>, material: usize| {
+            let mesh = Mesh::new(&device, &cube, Some(name));
+            let object = scene.add(&device, mesh, material, Mat4::IDENTITY);
+            let node = scene_graph.add_node(name, parent);
+            scene_graph.attach_object(node, object);
+            node
+        };
+
+        let base = add_cube("base", None, default_material);
+        let turret = add_cube("turret", Some(base), metal_material);
+        let left = add_cube("left", None, metal_material);
+        let right = add_cube("right", None, default_material);
+
+        scene_graph.set_translation(turret, Vec3::new(0.0, 0.75, 0.0));
+        scene_graph.set_scale(turret, Vec3::splat(0.5));
+        scene_graph.set_rotation(turret, Quat::from_rotation_y(0.8));
+
+        scene_graph.set_translation(left, Vec3::new(-1.5, 0.0, -1.0));
+        scene_graph.set_scale(left, Vec3::splat(0.5));
+        scene_graph.set_rotation(left, Quat::from_rotation_y(0.6));
+
+        scene_graph.set_translation(right, Vec3::new(1.5, 0.25, -2.0));
+        scene_graph.set_scale(right, Vec3::splat(0.75));
+        scene_graph.set_rotation(right, Quat::from_rotation_x(0.4));
+
+        // And something that isn't a cube, loaded through the OBJ parser.
+        // It has no UVs so it only gets the vertex colors
+        let pyramid = Model::from_obj(&include_bytes!("res/pyramid.obj")[..])
+            .expect("embedded pyramid.obj should parse");
+        let plain_material = scene.add_material(
+            &device,
+            MaterialParams::default(),
+            MaterialTextures::default(),
+            Some("plain_material"),
+        );
+        let pyramid_mesh = Mesh::new(&device, &pyramid, Some("pyramid"));
+        let pyramid_object = scene.add(&device, pyramid_mesh, plain_material, Mat4::IDENTITY);
+        let pyramid_node = scene_graph.add_node("pyramid", None);
+        scene_graph.attach_object(pyramid_node, pyramid_object);
+        scene_graph.set_translation(pyramid_node, Vec3::new(0.0, -0.5, -2.75));
+
+        scene_graph.sync(&mut scene);
+
+        // Lights, a sun plus a warm point light and a spot light
+        let shadow_map = ShadowMap::new(
+            &device,
+            &scene.object_bind_group_layout,
+            ShadowConfig::default(),
+        )?;
+        let mut lights = Lights::new(&device, &shadow_map);
+        lights.add(Light::directional(
+            Vec3::new(-0.4, -1.0, -0.6),
+            Vec3::new(1.0, 0.97, 0.9),
+            0.8,
+        ));
+        lights.add(Light::point(
+            Vec3::new(1.5, 1.0, 1.0),
+            Vec3::new(1.0, 0.6, 0.3),
+            1.5,
+        ));
+        lights.add(Light::spot(
+            Vec3::new(-1.5, 2.0, 0.5),
+            Vec3::new(0.0, -1.0, -0.5),
+            0.3,
+            0.5,
+            Vec3::new(0.4, 0.6, 1.0),
+            2.0,
+        ));
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &scene.material_layout.layout,
+                    &camera_bind_group_layout,
+                    &scene.object_bind_group_layout,
+                    &lights.bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let vert_shader_state = wgpu::VertexState {
-            module: &shader,
-            entry_point: Some("vs_main"),
-            buffers: &[Vert::desc()],
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-        };
-
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
@@ -173,97 +410,760 @@ impl State {
             .copied()
             .unwrap_or(surface_caps.formats[0]);
 
-        let frag_shader_state = wgpu::FragmentState {
-            module: &shader,
-            entry_point: Some("fs_main"),
-            targets: &[Some(wgpu::ColorTargetState {
-                format: surface_format,
-                // Set alpha mode so translucency works
-                blend: Some(wgpu::BlendState {
-                    color: wgpu::BlendComponent {
-                        src_factor: wgpu::BlendFactor::SrcAlpha,
-                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                        operation: wgpu::BlendOperation::Add,
-                    },
-                    alpha: wgpu::BlendComponent::OVER,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-        };
+        // Depth buffer
+        let depth_config = DepthConfig::default();
+        let hdr_format = hdr_format(&adapter);
 
-        let primitive_state = wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            polygon_mode: wgpu::PolygonMode::Fill,
-            unclipped_depth: false,
-            conservative: false,
+        // 4x is always there on WebGPU, fall back to none just in case
+        let sample_count =
+            if supported_sample_counts(&adapter, &device, hdr_format, &depth_config).contains(&4) {
+                4
+            } else {
+                1
+            };
+        let viewport = Viewport {
+            size,
+            sample_count,
+            depth_config,
+            hdr_format,
         };
+        let targets = SceneTargets::new(&device, &viewport);
+        let id_buffer = IdBuffer::new(
+            &device,
+            &camera_bind_group_layout,
+            &scene.object_bind_group_layout,
+            &viewport,
+        )?;
+        let outline = Outline::new(
+            &device,
+            &camera_bind_group_layout,
+            &scene.object_bind_group_layout,
+            &viewport,
+            OutlineConfig::default(),
+        )?;
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: vert_shader_state,
-            fragment: Some(frag_shader_state),
-            primitive: primitive_state,
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        });
+        let hdr = HdrTarget::new(&device, &viewport, surface_format, TonemapConfig::default())?;
+
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            hdr_format,
+            &depth_config,
+            sample_count,
+        );
+        let debug_pipelines = DebugPipelines::new(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            hdr_format,
+            &depth_config,
+            sample_count,
+        );
+
+        // Until a cubemap gets loaded the sky is a plain gradient
+        let sky_texture = Skybox::gradient_texture(
+            &device,
+            &queue,
+            Vec3::new(0.25, 0.45, 0.8),
+            Vec3::new(0.75, 0.82, 0.9),
+            Vec3::new(0.3, 0.28, 0.25),
+        );
+        let skybox = Skybox::new(
+            &device,
+            sky_texture,
+            hdr_format,
+            &depth_config,
+            sample_count,
+        )?;
 
         // Now create our state struct
         let state = Self {
             surface,
             surface_format,
+            adapter,
             device,
             queue,
             size,
             window,
             render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            diffuse_bind_group,
-            camera,
+            render_pipeline_layout,
+            shader,
+            shading_model,
+            render_mode: RenderMode::default(),
+            debug_pipelines,
+            depth_config,
+            targets,
+            sample_count,
+            hdr,
+            hdr_format,
+            default_material,
+            camera: camera.clone(),
             camera_uniform,
             camera_buffer,
             camera_bind_group,
             camera_controller,
-            model,
+            fixed_timestep: None,
+            previous_camera: camera.clone(),
+            rendered_camera: camera.clone(),
+            pick_mode: PickMode::default(),
+            selected: None,
+            hit: None,
+            id_buffer,
+            outline,
+            scene,
+            scene_graph,
+            cube_grid: None,
+            lights,
+            shadow_map,
+            skybox,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher: ShaderWatcher::from_env(),
         };
 
         state.config_surface();
 
-        state
+        Ok(state)
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config_surface();
+            self.viewport_changed();
+        }
+    }
+
+    // Swap the depth format/compare function and rebuild whatever depends on it
+    pub fn set_depth_config(&mut self, depth_config: DepthConfig) {
+        self.depth_config = depth_config;
 
-            self.camera = Camera::new(self.size.width as f32 / self.size.height as f32);
-            self.camera_uniform.update_view_proj(&self.camera);
+        // Revalidates the sample count against the new depth format
+        // and rebuilds the targets and pipelines along the way
+        self.set_sample_count(self.sample_count);
+    }
 
-            // Fix aspect ratio
-            self.camera.aspect = self.size.width as f32 / self.size.height as f32;
+    // Sample counts that both the HDR target and the depth format can do
+    pub fn supported_sample_counts(&self) -> Vec<u32> {
+        supported_sample_counts(
+            &self.adapter,
+            &self.device,
+            self.hdr_format,
+            &self.depth_config,
+        )
+    }
+
+    // Falls back to the highest supported count below `count`,
+    // returns the one actually in use
+    pub fn set_sample_count(&mut self, count: u32) -> u32 {
+        let supported = self.supported_sample_counts();
+        let sample_count = supported
+            .iter()
+            .copied()
+            .filter(|&c| c <= count)
+            .max()
+            .unwrap_or(1);
+        if sample_count != count {
+            log::warn!("{count}x MSAA is not supported, using {sample_count}x");
         }
+
+        self.sample_count = sample_count;
+        self.viewport_changed();
+        self.rebuild_pipelines();
+        sample_count
+    }
+
+    // Steps through the supported sample counts, wrapping around
+    pub fn cycle_sample_count(&mut self) -> u32 {
+        let supported = self.supported_sample_counts();
+        let next = supported
+            .iter()
+            .copied()
+            .find(|&c| c > self.sample_count)
+            .unwrap_or(1);
+        log::info!("MSAA {next}x");
+        self.set_sample_count(next)
+    }
+
+    pub fn cycle_render_mode(&mut self) {
+        self.render_mode = self.render_mode.next();
+        log::info!("Render mode {:?}", self.render_mode);
+    }
+
+    // Fly mode takes the cursor so mouse look can't run off the window
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_controller.mode = mode;
+        match mode {
+            CameraMode::Orbit => self.release_cursor(),
+            CameraMode::Fly => self.grab_cursor(),
+        }
+        log::info!("Camera mode {mode:?}");
+    }
+
+    pub fn toggle_fly_camera(&mut self) {
+        self.set_camera_mode(match self.camera_controller.mode {
+            CameraMode::Orbit => CameraMode::Fly,
+            CameraMode::Fly => CameraMode::Orbit,
+        });
+    }
+
+    // Selects whatever is under the cursor, or clears the selection.
+    // With the ID buffer that happens a frame or two later
+    pub fn select_at(&mut self, cursor: PhysicalPosition<f64>) {
+        match self.pick_mode {
+            PickMode::Ray => {
+                self.pick(cursor);
+            }
+            PickMode::IdBuffer => self.id_buffer.request(cursor.x as u32, cursor.y as u32),
+        }
+    }
+
+    pub fn toggle_pick_mode(&mut self) {
+        self.pick_mode = match self.pick_mode {
+            PickMode::Ray => PickMode::IdBuffer,
+            PickMode::IdBuffer => PickMode::Ray,
+        };
+        log::info!("Pick mode {:?}", self.pick_mode);
+    }
+
+    // Ray pick on the CPU, selects the hit instance
+    pub fn pick(&mut self, cursor: PhysicalPosition<f64>) -> Option<Hit> {
+        let ray = self.rendered_camera.ray(
+            Vec2::new(cursor.x as f32, cursor.y as f32),
+            Vec2::new(self.size.width as f32, self.size.height as f32),
+        );
+        self.hit = self.scene.pick(&ray);
+        self.selected = self.hit.map(|hit| Selection {
+            object: hit.object,
+            instance: hit.instance,
+        });
+
+        match &self.hit {
+            Some(hit) => log::info!(
+                "Picked object {:?} instance {} triangle {} at {:.3} normal {:.3}",
+                hit.object,
+                hit.instance,
+                hit.triangle,
+                hit.point,
+                hit.normal
+            ),
+            None => log::info!("Picked nothing"),
+        }
+        self.hit
+    }
+
+    // Takes the object out of the scene and whatever node it follows,
+    // dropping the selection if it pointed at it
+    pub fn remove_object(&mut self, id: ObjectId) -> bool {
+        let removed = self.scene.remove(id).is_some();
+        self.scene_graph.detach_object(id);
+        if self.selected.is_some_and(|selected| selected.object == id) {
+            self.selected = None;
+            self.hit = None;
+        }
+        removed
+    }
+
+    // Instanced objects lose just the selected instance,
+    // the object goes once it's down to its last one
+    pub fn remove_selected(&mut self) {
+        let Some(selected) = self.selected.take() else {
+            return;
+        };
+        self.hit = None;
+
+        let instance_count = self.scene.instances(selected.object).map_or(0, <[_]>::len);
+        if instance_count > 1 {
+            self.scene
+                .remove_instance(selected.object, selected.instance);
+            log::info!(
+                "Removed instance {} of object {:?}",
+                selected.instance,
+                selected.object
+            );
+        } else {
+            self.remove_object(selected.object);
+            log::info!("Removed object {:?}", selected.object);
+        }
+    }
+
+    // Projection and depth buffer have to agree on which way depth goes
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.camera.reverse_z = reverse_z;
+        self.previous_camera.reverse_z = reverse_z;
+        self.set_depth_config(DepthConfig {
+            compare: if reverse_z {
+                DepthConfig::reversed().compare
+            } else {
+                DepthConfig::default().compare
+            },
+            ..self.depth_config
+        });
+        log::info!("Reverse Z {reverse_z}");
+    }
+
+    pub fn toggle_reverse_z(&mut self) {
+        self.set_reverse_z(!self.camera.reverse_z);
+    }
+
+    pub fn toggle_projection(&mut self) {
+        self.camera.toggle_projection();
+        // Jumps shouldn't get interpolated
+        self.previous_camera = self.camera.clone();
+        log::info!("Projection {:?}", self.camera.projection);
     }
 
-    pub fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
+    pub fn set_view(&mut self, preset: ViewPreset) {
+        self.camera.set_view(preset);
+        self.previous_camera = self.camera.clone();
+    }
+
+    // Locked is pointer lock on the web, some desktop platforms only
+    // support confining the cursor to the window. Browsers drop the lock
+    // on their own (escape, tab switch), calling this again re-grabs
+    pub fn grab_cursor(&self) {
+        let grabbed = self
+            .window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined));
+        if let Err(err) = grabbed {
+            log::warn!("Can't grab the cursor: {err}");
+        }
+        self.window.set_cursor_visible(false);
+    }
+
+    pub fn release_cursor(&self) {
+        if let Err(err) = self.window.set_cursor_grab(CursorGrabMode::None) {
+            log::warn!("Can't release the cursor: {err}");
+        }
+        self.window.set_cursor_visible(true);
+    }
+
+    // Picks up shader edits when hot reloading is on
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = self.shader_watcher.as_mut() else {
+            return;
+        };
+        let changed = watcher.poll();
+        if changed.is_empty() {
+            return;
+        }
+
+        // Anything else is an include that any of them might use
+        let names: Vec<_> = if changed
+            .iter()
+            .all(|name| RELOADABLE_SHADERS.contains(&name.as_str()))
+        {
+            RELOADABLE_SHADERS
+                .iter()
+                .filter(|name| changed.iter().any(|changed| changed == *name))
+                .collect()
+        } else {
+            RELOADABLE_SHADERS.iter().collect()
+        };
+
+        // Includes come from disk as well
+        let preprocessor = Preprocessor::standard();
+        let changed: Vec<_> = names
+            .into_iter()
+            .filter_map(|&name| {
+                let source = preprocessor.process(name, |file| {
+                    watcher.read(file).map_err(|source| PreprocessError::Io {
+                        file: file.to_string(),
+                        source,
+                    })
+                });
+                match source {
+                    Ok(source) => Some((name, source)),
+                    Err(err) => {
+                        log::error!("{err}");
+                        log::warn!("Keeping the previous version of {name}");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        for (name, source) in changed {
+            let checked = ShaderReflection::new(name, &source).and_then(|reflection| {
+                if SCENE_SHADERS.contains(&name) {
+                    check_scene_shader(&reflection)
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(err) = checked {
+                log::error!("{err}");
+                log::warn!("Keeping the previous version of {name}");
+                continue;
+            }
+
+            let Some(shader) = hot_reload::compile(&self.device, name, &source) else {
+                log::warn!("Keeping the previous version of {name}");
+                continue;
+            };
+            let Some(previous) = self.replace_shader(name, shader) else {
+                continue;
+            };
+
+            // The shader itself is fine but might not fit the pipeline
+            // layout, in that case go back to what worked before
+            self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            self.rebuild_shader_pipeline(name);
+            if let Some(err) = self.device.pop_error_scope().block_on() {
+                log::error!("{name}: {err}");
+                log::warn!("Keeping the previous version of {name}");
+                self.replace_shader(name, previous);
+                self.rebuild_shader_pipeline(name);
+            } else {
+                log::info!("Reloaded {name}");
+            }
+        }
+    }
+
+    // Returns the old module, None for shaders that can't be reloaded
+    #[cfg(not(target_arch = "wasm32"))]
+    fn replace_shader(
+        &mut self,
+        name: &str,
+        shader: wgpu::ShaderModule,
+    ) -> Option<wgpu::ShaderModule> {
+        match name {
+            name if name == self.shading_model.shader() => {
+                Some(std::mem::replace(&mut self.shader, shader))
+            }
+            "wireframe.wgsl" => Some(self.debug_pipelines.replace_shader(shader)),
+            "skybox.wgsl" => Some(self.skybox.replace_shader(shader)),
+            "shadow.wgsl" => Some(self.shadow_map.replace_shader(shader)),
+            "tonemap.wgsl" => Some(self.hdr.replace_shader(shader)),
+            _ => None,
+        }
+    }
+
+    // The shadow and tonemap passes don't care about the viewport
+    // settings, so they aren't part of rebuild_pipelines
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rebuild_shader_pipeline(&mut self, name: &str) {
+        match name {
+            "shadow.wgsl" => self.shadow_map.rebuild_pipeline(&self.device),
+            "tonemap.wgsl" => self.hdr.rebuild_pipeline(&self.device),
+            _ => self.rebuild_pipelines(),
+        }
+    }
+
+    // Everything that has to match the surface size or sample count
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            size: self.size,
+            sample_count: self.sample_count,
+            depth_config: self.depth_config,
+            hdr_format: self.hdr_format,
+        }
+    }
+
+    // Passes a new size, sample count or depth config on to everything that
+    // depends on it. The camera only needs the aspect, where it looks stays
+    fn viewport_changed(&mut self) {
+        let viewport = self.viewport();
+        self.camera.aspect = viewport.aspect();
+        self.previous_camera.aspect = viewport.aspect();
+        self.rendered_camera.aspect = viewport.aspect();
+
+        let dependents: [&mut dyn ViewportDependent; 4] = [
+            &mut self.targets,
+            &mut self.hdr,
+            &mut self.id_buffer,
+            &mut self.outline,
+        ];
+        for dependent in dependents {
+            dependent.viewport_changed(&self.device, &viewport);
+        }
+    }
+
+    fn rebuild_pipelines(&mut self) {
+        self.render_pipeline = create_render_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            self.hdr_format,
+            &self.depth_config,
+            self.sample_count,
+        );
+        self.debug_pipelines.rebuild(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            self.hdr_format,
+            &self.depth_config,
+            self.sample_count,
+        );
+        self.skybox.rebuild_pipeline(
+            &self.device,
+            self.hdr_format,
+            &self.depth_config,
+            self.sample_count,
+        );
+    }
+
+    #[allow(dead_code)]
+    pub fn set_shading_model(&mut self, model: ShadingModel) {
+        if model == self.shading_model {
+            return;
+        }
+        self.shading_model = model;
+        self.shader = Preprocessor::standard().load_embedded(&self.device, model.shader());
+        self.rebuild_pipelines();
+        log::info!("Shading model: {model:?}");
+    }
+
+    // Pick the tonemapping operator and exposure
+    pub fn set_tonemap_config(&mut self, config: TonemapConfig) {
+        self.hdr.set_config(&self.queue, config);
+        log::info!(
+            "Tonemap {:?} exposure {:+.1}",
+            config.tonemap,
+            config.exposure
+        );
+    }
+
+    pub fn cycle_tonemap(&mut self) {
+        let mut config = self.hdr.config;
+        config.tonemap = config.tonemap.next();
+        self.set_tonemap_config(config);
+    }
+
+    // In stops, negative darkens
+    pub fn adjust_exposure(&mut self, steps: f32) {
+        let mut config = self.hdr.config;
+        config.exposure += steps * EXPOSURE_STEP;
+        self.set_tonemap_config(config);
+    }
+
+    // Outline color and width, plus the optional tint of the selection
+    #[allow(dead_code)]
+    pub fn set_outline_config(&mut self, config: OutlineConfig) {
+        self.outline.set_config(&self.queue, config);
+    }
+
+    // Replaces the sky with a cubemap, either a directory holding px.png,
+    // nx.png, py.png, ny.png, pz.png and nz.png or one cross layout image
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_skybox(&mut self, path: &std::path::Path) -> Result<(), CubemapError> {
+        let texture = if path.is_dir() {
+            let mut faces = Vec::with_capacity(6);
+            for name in ["px", "nx", "py", "ny", "pz", "nz"] {
+                let file = path.join(format!("{name}.png"));
+                faces.push(std::fs::read(file).map_err(image::ImageError::IoError)?);
+            }
+            let faces = std::array::from_fn(|face| faces[face].as_slice());
+            Texture::cube_from_bytes(&self.device, &self.queue, faces, Some("skybox"))?
+        } else {
+            let img = image::open(path)?;
+            Texture::cube_from_cross(&self.device, &self.queue, &img, Some("skybox"))?
+        };
+        self.skybox.set_texture(&self.device, texture);
+        Ok(())
+    }
+
+    // Adds a model using the default material, drawn once per instance in a
+    // single draw call. The returned node moves all of them, the object is
+    // for changing the instances later through Scene::set_instances and friends
+    pub fn add_instanced_model(
+        &mut self,
+        name: &str,
+        model: &Model,
+        instances: Vec<Instance>,
+        parent: Option<NodeId>,
+    ) -> (NodeId, ObjectId) {
+        let mesh = Mesh::new(&self.device, model, Some(name));
+        let object = self
+            .scene
+            .add(&self.device, mesh, self.default_material, Mat4::IDENTITY);
+        self.scene.set_instances(object, instances);
+        let node = self.scene_graph.add_node(name, parent);
+        self.scene_graph.attach_object(node, object);
+        (node, object)
+    }
+
+    // A floating grid of tinted cubes, all of them in one draw call
+    pub fn toggle_cube_grid(&mut self) {
+        const SIZE: usize = 10;
+
+        if let Some((node, object)) = self.cube_grid.take() {
+            self.scene_graph.remove_node(node);
+            // Unless its instances were all deleted already, then it comes back
+            if self.remove_object(object) {
+                log::info!("Cube grid off");
+                return;
+            }
+        }
+
+        let instances = (0..SIZE * SIZE)
+            .map(|index| {
+                let cell = Vec2::new((index % SIZE) as f32, (index / SIZE) as f32);
+                let t = cell / (SIZE - 1) as f32;
+                let transform = Mat4::from_scale_rotation_translation(
+                    Vec3::splat(0.3),
+                    Quat::from_rotation_y((cell.x + cell.y) * 0.3),
+                    Vec3::new(t.x - 0.5, 0.0, t.y - 0.5) * 4.0,
+                );
+                Instance::new(transform).with_tint(Vec4::new(t.x, 0.6, t.y, 1.0))
+            })
+            .collect();
+        let (node, object) =
+            self.add_instanced_model("cube_grid", &Model::cube(0.5), instances, None);
+        self.scene_graph
+            .set_translation(node, Vec3::new(0.0, 1.5, -1.0));
+        self.cube_grid = Some((node, object));
+        log::info!("Cube grid on, {} instances", SIZE * SIZE);
+    }
+
+    // Returns one scene graph node per glTF node
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn add_gltf(&mut self, gltf: &GltfScene, parent: Option<NodeId>) -> Vec<NodeId> {
+        let objects = self
+            .scene
+            .add_gltf(&self.device, gltf, self.default_material);
+        self.scene_graph.add_gltf(gltf, &objects, parent)
+    }
+
+    // Loads an OBJ file from disk, material libraries are looked up next to it
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_obj(&mut self, path: &std::path::Path) -> Result<NodeId, ObjError> {
+        use std::{fs::File, io::BufReader};
+
+        let dir = path.parent().unwrap_or(std::path::Path::new("."));
+        let model = Model::from_obj_with_mtl(BufReader::new(File::open(path)?), |name| {
+            File::open(dir.join(name)).map(|file| Some(BufReader::new(file)))
+        })?;
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("obj");
+
+        let materials: Vec<usize> = model
+            .materials
+            .iter()
+            .map(|mtl| {
+                let texture = mtl.diffuse_map.as_ref().and_then(|map| {
+                    image::open(dir.join(map))
+                        .map_err(|err| err.to_string())
+                        .and_then(|img| {
+                            Texture::from_image(&self.device, &self.queue, &img, Some(map))
+                                .map_err(|err| err.to_string())
+                        })
+                        .inspect_err(|err| log::warn!("Skipping texture {map}: {err}"))
+                        .ok()
+                });
+                self.scene.add_material(
+                    &self.device,
+                    MaterialParams::from_mtl(mtl),
+                    MaterialTextures {
+                        base_color: texture.as_ref(),
+                        ..Default::default()
+                    },
+                    Some(&mtl.name),
+                )
+            })
+            .collect();
+
+        // One object per group so every group gets its own material
+        let node = self.scene_graph.add_node(name, None);
+        for group in &model.groups {
+            let material = group
+                .material
+                .map_or(self.default_material, |index| materials[index]);
+            let mesh = Mesh::new(&self.device, &model.group(group), Some(&group.name));
+            let object = self.scene.add(&self.device, mesh, material, Mat4::IDENTITY);
+            self.scene_graph.attach_object(node, object);
+        }
+        self.scene_graph.sync(&mut self.scene);
+        Ok(node)
+    }
+
+    // Loads a .gltf or .glb file from disk with all of its materials
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_gltf(&mut self, path: &std::path::Path) -> Result<Vec<NodeId>, GltfError> {
+        let gltf = GltfScene::open(&self.device, &self.queue, path)?;
+        let nodes = self.add_gltf(&gltf, None);
+        self.scene_graph.sync(&mut self.scene);
+        Ok(nodes)
+    }
+
+    // `dt` is the measured frame time in seconds
+    pub fn update(&mut self, dt: f32) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_shaders();
+
+        if let Some(selected) = self.id_buffer.poll(&self.device) {
+            // The instance might have been removed while the pick was in flight
+            let selected = selected.filter(|selected| {
+                self.scene
+                    .instances(selected.object)
+                    .is_some_and(|instances| selected.instance < instances.len())
+            });
+            self.selected = selected;
+            self.hit = None;
+            log::info!("Picked {selected:?} from the ID buffer");
+        }
+
+        // What gets rendered this frame
+        let camera = match self.fixed_timestep.as_mut() {
+            None => {
+                self.camera_controller.update_camera(&mut self.camera, dt);
+                self.camera.clone()
+            }
+            Some(timestep) => {
+                for _ in 0..timestep.advance(dt) {
+                    self.previous_camera = self.camera.clone();
+                    self.camera_controller
+                        .update_camera(&mut self.camera, timestep.step());
+                }
+                self.previous_camera.lerp(&self.camera, timestep.alpha())
+            }
+        };
+
+        self.camera_uniform.update_view_proj(&camera);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+        self.scene_graph.sync(&mut self.scene);
+        self.scene.update(&self.device, &self.queue);
+        self.lights.update(&self.queue);
+        self.shadow_map
+            .update(&self.queue, self.lights.shadow_caster(), camera.target);
+        self.skybox.update(&self.queue, &camera);
+        self.rendered_camera = camera;
+    }
+
+    pub fn set_timestep(&mut self, timestep: Timestep) {
+        self.fixed_timestep = match timestep {
+            Timestep::Variable => None,
+            Timestep::Fixed(rate) => Some(FixedTimestep::new(rate)),
+        };
+        self.previous_camera = self.camera.clone();
+        log::info!("Timestep {timestep:?}");
+    }
+
+    pub fn toggle_fixed_timestep(&mut self) {
+        self.set_timestep(match self.fixed_timestep {
+            Some(_) => Timestep::Variable,
+            None => Timestep::Fixed(FIXED_UPDATE_RATE),
+        });
+    }
+
+    // Resolution, bias and filter size, the shadow map gets recreated
+    // when the resolution changes
+    #[allow(dead_code)]
+    pub fn set_shadow_config(&mut self, config: ShadowConfig) {
+        if self.shadow_map.set_config(&self.device, config) {
+            self.lights
+                .rebind_shadow_map(&self.device, &self.shadow_map);
+        }
+        log::info!("Shadows {:?}", self.shadow_map.config);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -280,42 +1180,75 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
-        let clear_color = wgpu::Color {
-            r: 0.2,
-            g: 0.2,
-            b: 0.5,
-            a: 1.0,
+        // Depth from the sun's point of view first
+        self.shadow_map.render(&mut encoder, &self.scene);
+        // Only does anything when a pick is waiting
+        self.id_buffer
+            .render(&self.queue, &mut encoder, &self.camera_uniform, &self.scene);
+
+        // With MSAA on the samples get resolved into the HDR target
+        // and don't need to stick around after the pass
+        let color_attachment = match &self.targets.msaa {
+            Some(msaa) => wgpu::RenderPassColorAttachment {
+                view: &msaa.view,
+                resolve_target: Some(self.hdr.view()),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Discard,
+                },
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: self.hdr.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            },
         };
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(clear_color),
+                color_attachments: &[Some(color_attachment)],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth_config.far_depth()),
                         store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
+                    }),
+                    stencil_ops: None,
+                }),
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
 
-            let index_len = self.model.indicies.len() as u32;
-            let index_amt = 0..index_len; //(INDICES.len() as u32);
-
             // Draw to pipeline
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
             render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(index_amt, 0, 0..1);
+            render_pass.set_bind_group(3, &self.lights.bind_group, &[]);
+            self.debug_pipelines
+                .draw(&mut render_pass, self.render_mode, &self.scene);
+
+            self.skybox.draw(&mut render_pass);
+        }
+
+        // The selection goes over the resolved scene, before tonemapping
+        if let Some(selected) = self.selected {
+            self.outline.render(
+                &mut encoder,
+                self.hdr.view(),
+                &self.camera_bind_group,
+                &self.scene,
+                selected,
+            );
         }
 
+        // Squash the HDR colors down to the surface
+        self.hdr.render(&mut encoder, &view);
+
         // Submit our queue and then render it
         self.queue.submit(iter::once(encoder.finish()));
+        self.id_buffer.submitted();
         output.present();
         Ok(())
     }
@@ -338,3 +1271,117 @@ impl State {
         self.surface.configure(&self.device, &config);
     }
 }
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_config: &DepthConfig,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let vert_shader_state = wgpu::VertexState {
+        module: shader,
+        entry_point: Some("vs_main"),
+        buffers: &[Vert::desc(), InstanceRaw::desc()],
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+    };
+
+    let frag_shader_state = wgpu::FragmentState {
+        module: shader,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+            format: color_format,
+            // Set alpha mode so translucency works
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        })],
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+    };
+
+    let primitive_state = wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        strip_index_format: None,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode: Some(wgpu::Face::Back),
+        polygon_mode: wgpu::PolygonMode::Fill,
+        unclipped_depth: false,
+        conservative: false,
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: vert_shader_state,
+        fragment: Some(frag_shader_state),
+        primitive: primitive_state,
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_config.format,
+            depth_write_enabled: true,
+            depth_compare: depth_config.compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    hdr_format: wgpu::TextureFormat,
+    depth_config: &DepthConfig,
+) -> Vec<u32> {
+    let color = adapter.get_texture_format_features(hdr_format).flags;
+    let depth = adapter
+        .get_texture_format_features(depth_config.format)
+        .flags;
+    // Without the adapter specific feature only what WebGPU guarantees is allowed
+    let adapter_specific = device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+    // The MSAA target gets resolved into the HDR one, so that has to work too
+    let resolve = color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (resolve
+                    && color.sample_count_supported(count)
+                    && depth.sample_count_supported(count)
+                    && (adapter_specific || count == 4))
+        })
+        .collect()
+}
+
+// Layouts of the main pipeline in group order
+fn scene_bind_group_descs<'a>() -> [wgpu::BindGroupLayoutDescriptor<'a>; 4] {
+    [
+        MaterialLayout::bind_desc(),
+        CameraUniform::new().bind_desc(),
+        ObjectUniform::bind_desc(),
+        LightUniform::bind_desc(),
+    ]
+}
+
+fn check_scene_shader(reflection: &ShaderReflection) -> Result<(), ReflectError> {
+    reflection.validate(
+        &scene_bind_group_descs(),
+        &[Vert::desc(), InstanceRaw::desc()],
+    )
+}
@@ -1,4 +1,55 @@
-use image::GenericImageView;
+use std::fmt;
+
+use image::{DynamicImage, GenericImageView, imageops};
+
+// Errors from building a cube texture out of images
+#[derive(Debug)]
+pub enum CubemapError {
+    Image(image::ImageError),
+    FaceSize {
+        face: usize,
+        width: u32,
+        height: u32,
+    },
+    CrossLayout {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl fmt::Display for CubemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CubemapError::Image(err) => write!(f, "image error: {err}"),
+            CubemapError::FaceSize {
+                face,
+                width,
+                height,
+            } => write!(
+                f,
+                "cube face {face} is {width}x{height}, faces have to be square and the same size"
+            ),
+            CubemapError::CrossLayout { width, height } => {
+                write!(f, "{width}x{height} is not a 4:3 or 3:4 cross layout")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CubemapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CubemapError::Image(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for CubemapError {
+    fn from(err: image::ImageError) -> Self {
+        CubemapError::Image(err)
+    }
+}
 
 pub struct Texture {
     #[allow(dead_code)]
@@ -8,11 +59,31 @@ pub struct Texture {
 }
 
 impl Texture {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self, wgpu::Error> {
+        Self::from_image_format(
+            device,
+            queue,
+            img,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            label,
+        )
+    }
+
+    // Data textures (normal maps, metallic/roughness...) have to skip
+    // the sRGB conversion, so they use Rgba8Unorm instead
+    pub fn from_image_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
     ) -> Result<Self, wgpu::Error> {
         let bytes = img.to_rgba8();
         let (width, height) = img.dimensions();
@@ -31,7 +102,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             label,
             view_formats: &[],
@@ -74,27 +145,259 @@ impl Texture {
         })
     }
 
-    pub fn bind_desc<'a>(&self, label: Option<&'a str>) -> wgpu::BindGroupLayoutDescriptor<'a> {
-        wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
+    // 1x1 texture, used as a stand in for missing material maps
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        // Creating the texture itself can't fail
+        Self::from_image_format(device, queue, &img, format, label).unwrap()
+    }
+
+    // Faces go in the order +X, -X, +Y, -Y, +Z, -Z
+    pub fn cube_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[DynamicImage; 6],
+        label: Option<&str>,
+    ) -> Result<Self, CubemapError> {
+        let size = faces[0].width();
+        for (face, img) in faces.iter().enumerate() {
+            let (width, height) = img.dimensions();
+            if width != size || height != size {
+                return Err(CubemapError::FaceSize {
+                    face,
+                    width,
+                    height,
+                });
+            }
+        }
+
+        let tex_extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: tex_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label,
+            view_formats: &[],
+        });
+
+        // Each face is its own array layer
+        for (layer, img) in faces.iter().enumerate() {
+            let bytes = img.to_rgba8();
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
                     },
-                    count: None,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &bytes,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
                 },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..tex_extent
                 },
-            ],
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+
+        // Clamp so the seams between faces don't bleed
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    // Six encoded images (PNGs) in +X, -X, +Y, -Y, +Z, -Z order.
+    // Only the desktop build loads skyboxes from disk
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn cube_from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [&[u8]; 6],
+        label: Option<&str>,
+    ) -> Result<Self, CubemapError> {
+        let mut images: Vec<DynamicImage> = Vec::with_capacity(6);
+        for bytes in faces {
+            images.push(image::load_from_memory(bytes)?);
+        }
+        let images: [DynamicImage; 6] = images.try_into().unwrap();
+
+        Self::cube_from_images(device, queue, &images, label)
+    }
+
+    // Single image with the faces laid out as a cross, either
+    // horizontal (4:3) or vertical (3:4):
+    //
+    //        +Y                  +Y
+    //     -X +Z +X -Z         -X +Z +X
+    //        -Y                  -Y
+    //                            -Z (upside down)
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn cube_from_cross(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self, CubemapError> {
+        let (width, height) = img.dimensions();
+
+        let horizontal = width * 3 == height * 4;
+        let vertical = width * 4 == height * 3;
+        if !horizontal && !vertical {
+            return Err(CubemapError::CrossLayout { width, height });
+        }
+
+        let size = if horizontal { width / 4 } else { width / 3 };
+        let face = |x: u32, y: u32| img.crop_imm(x * size, y * size, size, size);
+
+        let neg_z = if horizontal {
+            face(3, 1)
+        } else {
+            DynamicImage::ImageRgba8(imageops::rotate180(&face(1, 3)))
+        };
+
+        let faces = [
+            face(2, 1), // +X
+            face(0, 1), // -X
+            face(1, 0), // +Y
+            face(1, 2), // -Y
+            face(1, 1), // +Z
+            neg_z,      // -Z
+        ];
+
+        Self::cube_from_images(device, queue, &faces, label)
+    }
+
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: Option<&str>,
+    ) -> Self {
+        // Depth texture has to match the size of the surface we render to
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Comparison sampler in case we ever want to sample the depth
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // Color texture that gets rendered to and then read by a later pass
+    // Multisampled ones get resolved instead of read directly
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
         }
     }
 }
@@ -0,0 +1,93 @@
+// Frame timing. std::time::Instant panics on wasm32, web_time uses
+// performance.now() there and is just std on desktop
+use web_time::Instant;
+
+// A long stall (dragging the window, a breakpoint, a background tab)
+// would otherwise throw everything forward in one go
+const MAX_FRAME_TIME: f32 = 0.25;
+
+pub struct FrameTimer {
+    last: Option<Instant>,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self { last: None }
+    }
+
+    // Seconds since the previous tick, zero on the first one
+    pub fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let dt = self
+            .last
+            .map_or(0.0, |last| (now - last).as_secs_f32().min(MAX_FRAME_TIME));
+        self.last = Some(now);
+        dt
+    }
+}
+
+// How State::update advances the simulation
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Timestep {
+    // One update per frame with the measured delta
+    #[default]
+    Variable,
+    // Updates at a fixed rate (in Hz), rendering interpolates between the
+    // last two steps so motion stays smooth at any frame rate
+    Fixed(f32),
+}
+
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(rate: f32) -> Self {
+        Self {
+            step: 1.0 / rate.max(1.0),
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    // Adds a frame's worth of time, returns how many steps to run
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulator += dt;
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        steps
+    }
+
+    // How far the leftover time is into the next step, 0 to 1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_timestep_carries_leftover_time() {
+        // Powers of two so the float math comes out exact
+        let mut timestep = FixedTimestep::new(4.0);
+        assert_eq!(timestep.step(), 0.25);
+
+        assert_eq!(timestep.advance(0.625), 2);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(0.125), 0);
+        assert_eq!(timestep.alpha(), 0.5);
+    }
+
+    #[test]
+    fn fixed_timestep_rate_is_clamped() {
+        assert_eq!(FixedTimestep::new(0.0).step(), 1.0);
+    }
+}
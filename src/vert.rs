@@ -10,6 +10,7 @@ pub struct Vert {
     pub pos: [f32; 4],        // Vec4
     pub color: [f32; 4],      // Vec4
     pub tex_coords: [f32; 2], // Vec2
+    pub normal: [f32; 3],     // Vec3
 }
 
 impl Vert {
@@ -18,9 +19,15 @@ impl Vert {
             pos: pos.into(),
             color: color.into(),
             tex_coords: uv.into(),
+            normal: [0.0; 3],
         }
     }
 
+    pub fn with_normal<N: Into<[f32; 3]>>(mut self, normal: N) -> Self {
+        self.normal = normal.into();
+        self
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         // Return a buffer layout describing our verticies
         wgpu::VertexBufferLayout {
@@ -45,6 +52,12 @@ impl Vert {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                // Normal
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 10]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
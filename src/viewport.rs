@@ -0,0 +1,73 @@
+// Everything that gets rendered at window resolution. When the size,
+// sample count or depth config change State hands the new Viewport to
+// each ViewportDependent, new size dependent resources implement the
+// trait and get added to State::viewport_changed
+use winit::dpi::PhysicalSize;
+
+use crate::state::DepthConfig;
+use crate::texture::Texture;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub size: PhysicalSize<u32>,
+    pub sample_count: u32,
+    pub depth_config: DepthConfig,
+    // Color format of the scene targets, see hdr::hdr_format
+    pub hdr_format: wgpu::TextureFormat,
+}
+
+impl Viewport {
+    pub fn aspect(&self) -> f32 {
+        self.size.width as f32 / self.size.height as f32
+    }
+}
+
+pub trait ViewportDependent {
+    fn viewport_changed(&mut self, device: &wgpu::Device, viewport: &Viewport);
+}
+
+// Depth buffer and multisampled color target of the main pass
+pub struct SceneTargets {
+    pub depth: Texture,
+    // Only exists when sample_count > 1, gets resolved into the HDR target
+    pub msaa: Option<Texture>,
+}
+
+impl SceneTargets {
+    pub fn new(device: &wgpu::Device, viewport: &Viewport) -> Self {
+        Self {
+            depth: create_depth_texture(device, viewport),
+            msaa: create_msaa_texture(device, viewport),
+        }
+    }
+}
+
+impl ViewportDependent for SceneTargets {
+    fn viewport_changed(&mut self, device: &wgpu::Device, viewport: &Viewport) {
+        *self = Self::new(device, viewport);
+    }
+}
+
+fn create_depth_texture(device: &wgpu::Device, viewport: &Viewport) -> Texture {
+    Texture::create_depth_texture(
+        device,
+        viewport.size.width,
+        viewport.size.height,
+        viewport.depth_config.format,
+        viewport.sample_count,
+        Some("depth_texture"),
+    )
+}
+
+fn create_msaa_texture(device: &wgpu::Device, viewport: &Viewport) -> Option<Texture> {
+    (viewport.sample_count > 1).then(|| {
+        Texture::create_render_target(
+            device,
+            viewport.size.width,
+            viewport.size.height,
+            viewport.hdr_format,
+            viewport.sample_count,
+            Some("msaa_texture"),
+        )
+    })
+}